@@ -0,0 +1,2 @@
+-- Per-user DNS-over-TLS token, used as the SNI label in <token>.dns.heaven.computer
+ALTER TABLE users ADD COLUMN IF NOT EXISTS dot_token TEXT UNIQUE;
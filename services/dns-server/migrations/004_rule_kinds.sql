@@ -0,0 +1,3 @@
+-- Typed rules: exact, suffix (domain + subdomains), glob pattern, or allow override
+ALTER TABLE user_rules ADD COLUMN IF NOT EXISTS kind TEXT NOT NULL DEFAULT 'suffix'
+    CHECK (kind IN ('exact', 'suffix', 'glob', 'allow'));
@@ -0,0 +1,7 @@
+-- Community blocklist subscriptions (list IDs come from the BLOCKLISTS config)
+CREATE TABLE IF NOT EXISTS user_blocklists (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    list_id TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    PRIMARY KEY (user_id, list_id)
+);
@@ -0,0 +1,7 @@
+-- Optional device scope for rules (NULL = applies to all of the user's devices)
+ALTER TABLE user_rules ADD COLUMN IF NOT EXISTS device_id UUID REFERENCES devices(id) ON DELETE CASCADE;
+
+-- Uniqueness is now per scope: the same domain may have a user-wide and a device rule
+ALTER TABLE user_rules DROP CONSTRAINT IF EXISTS user_rules_user_id_domain_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_rules_scope_domain
+    ON user_rules (user_id, COALESCE(device_id, '00000000-0000-0000-0000-000000000000'::uuid), domain);
@@ -0,0 +1,2 @@
+-- Optional weekly schedule per rule: {"days":["Mon",...],"start":"09:00","end":"17:00","timezone":"Europe/Berlin"}
+ALTER TABLE user_rules ADD COLUMN IF NOT EXISTS schedule JSONB;
@@ -0,0 +1,4 @@
+-- Category rules: kind = 'category' with the category slug (e.g. 'social') in the domain column
+ALTER TABLE user_rules DROP CONSTRAINT IF EXISTS user_rules_kind_check;
+ALTER TABLE user_rules ADD CONSTRAINT user_rules_kind_check
+    CHECK (kind IN ('exact', 'suffix', 'glob', 'allow', 'category'));
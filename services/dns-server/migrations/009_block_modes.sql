@@ -0,0 +1,5 @@
+-- Block response mode, per user (default for all their rules) and per rule (override)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS block_mode TEXT
+    CHECK (block_mode IN ('null_ip', 'nxdomain', 'refused', 'sinkhole'));
+ALTER TABLE user_rules ADD COLUMN IF NOT EXISTS block_mode TEXT
+    CHECK (block_mode IN ('null_ip', 'nxdomain', 'refused', 'sinkhole'));
@@ -0,0 +1,7 @@
+-- Interest logging preferences per user and per device (stricter of the two applies)
+ALTER TABLE users ADD COLUMN IF NOT EXISTS logging_mode TEXT NOT NULL DEFAULT 'full'
+    CHECK (logging_mode IN ('full', 'categories_only', 'off'));
+ALTER TABLE users ADD COLUMN IF NOT EXISTS logging_paused_until TIMESTAMPTZ;
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS logging_mode TEXT NOT NULL DEFAULT 'full'
+    CHECK (logging_mode IN ('full', 'categories_only', 'off'));
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS logging_paused_until TIMESTAMPTZ;
@@ -0,0 +1,28 @@
+-- Event storage for EVENT_SINK=postgres
+-- Monthly partitions (dns_events_yYYYYmMM) are created by the gateway on first write
+
+CREATE TABLE IF NOT EXISTS dns_events (
+    ts TIMESTAMPTZ NOT NULL,
+    wallet_id TEXT NOT NULL,
+    device_id UUID NOT NULL,
+    etld1 TEXT NOT NULL,
+    domain_hmac TEXT NOT NULL,
+    qtype TEXT NOT NULL,
+    action TEXT NOT NULL,
+    category_ids INT[] NOT NULL DEFAULT '{}',
+    category_weights REAL[] NOT NULL DEFAULT '{}',
+    latency_ms INT NOT NULL
+) PARTITION BY RANGE (ts);
+
+CREATE INDEX IF NOT EXISTS idx_dns_events_wallet_ts ON dns_events(wallet_id, ts);
+
+CREATE TABLE IF NOT EXISTS interest_histograms (
+    window_start TIMESTAMPTZ NOT NULL,
+    window_end TIMESTAMPTZ NOT NULL,
+    wallet_id TEXT NOT NULL,
+    category_id INT NOT NULL,
+    weight DOUBLE PRECISION NOT NULL,
+    epsilon DOUBLE PRECISION
+);
+
+CREATE INDEX IF NOT EXISTS idx_interest_histograms_wallet ON interest_histograms(wallet_id, window_start);
@@ -0,0 +1,2 @@
+-- HMAC key version for domain_hmac (EVENT_SINK=postgres)
+ALTER TABLE dns_events ADD COLUMN IF NOT EXISTS key_id TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,4 @@
+-- Dedup counts and browsing sessions on stored events (EVENT_SINK=postgres)
+ALTER TABLE dns_events ADD COLUMN IF NOT EXISTS count INT NOT NULL DEFAULT 1;
+ALTER TABLE dns_events ADD COLUMN IF NOT EXISTS session_id UUID;
+ALTER TABLE dns_events ADD COLUMN IF NOT EXISTS session_start BOOLEAN NOT NULL DEFAULT FALSE;
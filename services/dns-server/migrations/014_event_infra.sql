@@ -0,0 +1,2 @@
+-- Infrastructure (CDN/analytics/telemetry) flag on stored events (EVENT_SINK=postgres)
+ALTER TABLE dns_events ADD COLUMN IF NOT EXISTS infra BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,267 @@
+//! Operator API under /admin
+//!
+//! Requests authenticate with `Authorization: Bearer <token>`, where the
+//! token is either ADMIN_TOKEN or a user JWT whose wallet is listed in
+//! ADMIN_WALLETS. With neither configured the whole router answers 404.
+
+use super::{deprovision_device, AuthUser, RevokedDevice, StatsResponse};
+use crate::AppState;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/users", get(list_users))
+        .route("/devices", get(list_devices))
+        .route("/devices/:id", delete(revoke_device))
+        .route("/reload", post(reload_caches))
+        .route("/heaven/flush", post(flush_heaven))
+        .route("/stats", get(stats))
+}
+
+/// Extractor for requests made with admin credentials
+pub struct AdminUser;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AdminUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let config = &state.config;
+        if config.admin_token.is_none() && config.admin_wallets.is_none() {
+            return Err((StatusCode::NOT_FOUND, "Admin API not configured".to_string()));
+        }
+
+        if let Some(expected) = &config.admin_token {
+            let presented = parts
+                .headers
+                .get("Authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "));
+            if presented.is_some_and(|t| token_matches(t, expected)) {
+                return Ok(AdminUser);
+            }
+        }
+
+        let AuthUser(claims) = AuthUser::from_request_parts(parts, state).await?;
+        if wallet_allowed(config.admin_wallets.as_deref(), &claims.sub) {
+            tracing::info!(wallet = %claims.sub, "Admin request");
+            Ok(AdminUser)
+        } else {
+            Err((StatusCode::FORBIDDEN, "Not an admin".to_string()))
+        }
+    }
+}
+
+/// Compare tokens in constant time (HMAC tag verification)
+fn token_matches(presented: &str, expected: &str) -> bool {
+    let tag = |key: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(b"admin-token");
+        mac
+    };
+    tag(presented)
+        .verify_slice(&tag(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+/// Whether `wallet` is in the comma-separated ADMIN_WALLETS list (case-insensitive)
+fn wallet_allowed(list: Option<&str>, wallet: &str) -> bool {
+    list.unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .any(|w| !w.is_empty() && w.eq_ignore_ascii_case(wallet))
+}
+
+#[derive(Serialize)]
+struct AdminUserEntry {
+    user_id: Uuid,
+    wallet_address: String,
+    created_at: Option<DateTime<Utc>>,
+    devices: i64,
+}
+
+async fn list_users(
+    _admin: AdminUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<AdminUserEntry>>, (StatusCode, String)> {
+    let rows = sqlx::query_as::<_, (Uuid, String, Option<DateTime<Utc>>, i64)>(
+        r#"
+        SELECT u.id, u.wallet_address, u.created_at, COUNT(d.id)
+        FROM users u
+        LEFT JOIN devices d ON d.user_id = u.id
+        GROUP BY u.id
+        ORDER BY u.created_at
+        "#,
+    )
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|(user_id, wallet_address, created_at, devices)| AdminUserEntry {
+                user_id,
+                wallet_address,
+                created_at,
+                devices,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct DevicesQuery {
+    user_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+struct AdminDeviceEntry {
+    device_id: Uuid,
+    user_id: Uuid,
+    wallet_address: String,
+    device_name: String,
+    wg_pubkey: String,
+    vpn_ip: String,
+    created_at: Option<DateTime<Utc>>,
+    last_dns_at: Option<String>,
+    connected: bool,
+}
+
+async fn list_devices(
+    _admin: AdminUser,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DevicesQuery>,
+) -> Result<Json<Vec<AdminDeviceEntry>>, (StatusCode, String)> {
+    let rows = sqlx::query_as::<_, (Uuid, Uuid, String, String, String, String, Option<DateTime<Utc>>)>(
+        r#"
+        SELECT d.id, d.user_id, u.wallet_address, d.device_name, d.wg_pubkey, host(d.vpn_ip), d.created_at
+        FROM devices d
+        JOIN users u ON u.id = d.user_id
+        WHERE $1::uuid IS NULL OR d.user_id = $1
+        ORDER BY d.created_at
+        "#,
+    )
+    .bind(query.user_id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(
+                |(device_id, user_id, wallet_address, device_name, wg_pubkey, vpn_ip, created_at)| {
+                    AdminDeviceEntry {
+                        device_id,
+                        user_id,
+                        wallet_address,
+                        device_name,
+                        wg_pubkey,
+                        vpn_ip,
+                        created_at,
+                        last_dns_at: state.last_seen.get(&device_id).map(|t| t.to_rfc3339()),
+                        connected: state.last_seen.is_connected(&device_id, 5),
+                    }
+                },
+            )
+            .collect(),
+    ))
+}
+
+async fn revoke_device(
+    _admin: AdminUser,
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<RevokedDevice>, (StatusCode, String)> {
+    deprovision_device(&state, device_id).await.map(Json)
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    cached_users: usize,
+    rule_sets: usize,
+    category_mappings: usize,
+}
+
+/// Re-read users, devices and rules from the database and the category map
+/// from its file, e.g. after manual edits (same as SIGHUP for the map)
+async fn reload_caches(
+    _admin: AdminUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ReloadResponse>, (StatusCode, String)> {
+    state
+        .user_cache
+        .load_from_db(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("User cache reload failed: {:#}", e)))?;
+    state
+        .rules_cache
+        .load_from_db(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Rules cache reload failed: {}", e)))?;
+    let category_mappings = state
+        .category_map
+        .reload()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Category map reload failed: {:#}", e)))?;
+
+    Ok(Json(ReloadResponse {
+        cached_users: state.user_cache.len(),
+        rule_sets: state.rules_cache.len().await,
+        category_mappings,
+    }))
+}
+
+#[derive(Serialize)]
+struct FlushResponse {
+    flushed: usize,
+}
+
+async fn flush_heaven(
+    _admin: AdminUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<FlushResponse>, (StatusCode, String)> {
+    let heaven = state
+        .heaven
+        .as_ref()
+        .ok_or((StatusCode::NOT_FOUND, "Heaven resolver not enabled".to_string()))?;
+    let flushed = heaven.clear_cache();
+    tracing::info!(flushed, "Heaven cache flushed");
+    Ok(Json(FlushResponse { flushed }))
+}
+
+async fn stats(_admin: AdminUser, state: State<Arc<AppState>>) -> Json<StatsResponse> {
+    super::get_stats(state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_credentials() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cre", "s3cret"));
+        assert!(!token_matches("", "s3cret"));
+
+        let list = Some("0xAbC, 0xdef,");
+        assert!(wallet_allowed(list, "0xabc"));
+        assert!(wallet_allowed(list, "0xDEF"));
+        assert!(!wallet_allowed(list, "0x123"));
+        assert!(!wallet_allowed(list, ""));
+        assert!(!wallet_allowed(None, "0xabc"));
+    }
+}
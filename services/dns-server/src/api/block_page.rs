@@ -0,0 +1,67 @@
+//! "Blocked by Heaven" page for sinkhole mode
+//!
+//! Blocked names resolve to BLOCK_SINKHOLE_IPV4/IPV6, where this listener
+//! answers every plain-HTTP request with a short explanation. HTTPS requests
+//! can't be served without a certificate for the blocked name, so browsers
+//! show a connection error there instead.
+
+use crate::AppState;
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    Router,
+};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) -> anyhow::Result<()> {
+    let Some(listen) = state.config.block_page_listen.as_deref() else {
+        return Ok(());
+    };
+
+    let app = Router::new().fallback(blocked);
+
+    let addr: std::net::SocketAddr = listen.parse()?;
+    tracing::info!("Block page listening on {}", addr);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.recv().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn blocked(headers: HeaderMap) -> impl IntoResponse {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(|h| h.split(':').next().unwrap_or(h))
+        .unwrap_or("This site");
+
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(format!(
+            "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Blocked by Heaven</title></head>\n\
+             <body style=\"font-family:sans-serif;text-align:center;margin-top:20vh\">\n\
+             <h1>Blocked by Heaven</h1>\n<p>{} is blocked by your rules.</p>\n</body></html>\n",
+            escape_html(host)
+        )),
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
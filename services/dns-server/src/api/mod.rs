@@ -1,14 +1,22 @@
 //! HTTP API for user management and WireGuard config
 
+pub mod admin;
+pub mod block_page;
+
 use crate::auth::Claims;
+use crate::dns::doh;
+use crate::logging_prefs::{LoggingMode, LoggingSettings};
+use crate::rules::{BlockMode, Rule, RuleKind, Schedule};
 use crate::AppState;
 use axum::{
     async_trait,
     extract::{ConnectInfo, FromRequestParts, Path, State},
-    http::{request::Parts, StatusCode},
-    routing::{get, post},
+    http::{header, request::Parts, StatusCode},
+    response::IntoResponse,
+    routing::{get, patch, post},
     Json, Router,
 };
+use chrono::{DateTime, Utc};
 use std::net::SocketAddr;
 use tower_http::cors::{Any, CorsLayer};
 use serde::{Deserialize, Serialize};
@@ -21,6 +29,8 @@ use tokio::sync::broadcast;
 use uuid::Uuid;
 
 const WG_CONFIG_PATH: &str = "/config/wg_confs/wg0.conf";
+/// Longest logging pause a client may request (one year)
+const MAX_PAUSE_MINUTES: i64 = 365 * 24 * 60;
 
 pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) -> anyhow::Result<()> {
     // CORS - allow browser requests from any origin (desktop VPN client auth flow)
@@ -36,12 +46,25 @@ pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) ->
         .route("/auth/verify", post(auth_verify))
         .route("/auth/mobile-handoff", post(mobile_handoff))
         .route("/auth/mobile-exchange", post(mobile_exchange))
-        .route("/devices", post(create_device))
+        .route("/dot-token", post(rotate_dot_token))
+        .route("/devices", get(list_devices).post(create_device))
+        .route("/devices/:id", patch(update_device).delete(delete_device))
+        .route("/devices/:id/rotate-key", post(rotate_device_key))
         .route("/devices/:id/wg-config", get(get_wg_config))
         .route("/devices/:id/status", get(get_device_status))
+        .route("/devices/:id/rules", get(get_device_rules).post(set_device_rules))
+        .route("/devices/:id/logging", get(get_device_logging).post(set_device_logging))
         .route("/rules", get(get_rules))
         .route("/rules", post(set_rules))
+        .route("/rules/block-mode", post(set_block_mode))
+        .route("/logging", get(get_logging).post(set_logging))
+        .route("/blocklists", get(get_blocklists))
+        .route("/blocklists/subscriptions", post(set_blocklist_subscriptions))
         .route("/stats", get(get_stats))
+        .route("/metrics", get(get_metrics))
+        .nest("/admin", admin::router())
+        // DNS-over-HTTPS (RFC 8484)
+        .route("/dns-query", get(doh::doh_get).post(doh::doh_post))
         // Dev endpoint - single call to register and get config (no auth)
         .route("/dev/quick-connect", post(dev_quick_connect))
         .layer(cors)
@@ -324,6 +347,44 @@ async fn mobile_exchange(
     }))
 }
 
+// Issue (or rotate) the user's DNS-over-TLS hostname (requires JWT)
+#[derive(Serialize)]
+struct DotTokenResponse {
+    token: String,
+    hostname: String,
+}
+
+async fn rotate_dot_token(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<DotTokenResponse>, (StatusCode, String)> {
+    // 128 bits of randomness, fits in a single DNS label
+    let token = format!("{:032x}", rand::random::<u128>());
+
+    sqlx::query("UPDATE users SET dot_token = $2 WHERE id = $1")
+        .bind(claims.user_id)
+        .bind(&token)
+        .execute(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    state.user_cache.set_dot_token(
+        token.clone(),
+        crate::users::CachedUser {
+            user_id: claims.user_id,
+            wallet_address: claims.sub.clone(),
+            device_id: Uuid::nil(),
+            vpn_ip: std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        },
+    );
+
+    let hostname = format!("{}.{}", token, state.config.dot_sni_suffix);
+
+    tracing::info!(user_id = %claims.user_id, "DoT token rotated");
+
+    Ok(Json(DotTokenResponse { token, hostname }))
+}
+
 // Create device (requires JWT)
 #[derive(Deserialize)]
 struct CreateDeviceRequest {
@@ -552,7 +613,7 @@ fn remove_peer_from_config(pubkey: &str) -> Result<(), String> {
         if line.trim() == "[Peer]" {
             if in_peer {
                 if current_pubkey.as_deref() != Some(pubkey) {
-                    output.extend(current_block.drain(..));
+                    output.append(&mut current_block);
                 } else {
                     current_block.clear();
                 }
@@ -573,10 +634,8 @@ fn remove_peer_from_config(pubkey: &str) -> Result<(), String> {
         }
     }
 
-    if in_peer {
-        if current_pubkey.as_deref() != Some(pubkey) {
-            output.extend(current_block);
-        }
+    if in_peer && current_pubkey.as_deref() != Some(pubkey) {
+        output.extend(current_block);
     }
 
     let mut updated = output.join("\n");
@@ -593,6 +652,291 @@ fn remove_peer_from_config(pubkey: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// A device row removed by `deprovision_device`
+#[derive(Serialize)]
+struct RevokedDevice {
+    device_id: Uuid,
+    user_id: Uuid,
+    wg_pubkey: String,
+    vpn_ip: String,
+}
+
+/// Delete a device and tear down what was provisioned for it: the WireGuard
+/// peer (live and in wg0.conf), its route, its cached VPN IP, its
+/// device-scoped rules and logging settings, and its last-seen time. Rule
+/// rows go with the device through ON DELETE CASCADE. The row is only deleted
+/// once wg0.conf no longer lists the peer, so a failed teardown leaves the
+/// device working.
+async fn deprovision_device(
+    state: &AppState,
+    device_id: Uuid,
+) -> Result<RevokedDevice, (StatusCode, String)> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let (user_id, wg_pubkey, vpn_ip) = sqlx::query_as::<_, (Uuid, String, String)>(
+        "DELETE FROM devices WHERE id = $1 RETURNING user_id, wg_pubkey, host(vpn_ip)",
+    )
+    .bind(device_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Device not found".to_string()))?;
+
+    if let Err(e) = remove_peer_from_config(&wg_pubkey) {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("WireGuard deprovisioning failed: {}", e),
+        ));
+    }
+
+    if let Err(e) = tx.commit().await {
+        // The live peer is still there; put it back in wg0.conf too
+        if let Err(restore_err) = persist_wireguard_peer(&wg_pubkey, &vpn_ip) {
+            tracing::warn!(
+                pubkey = %wg_pubkey,
+                error = %restore_err,
+                "Failed to restore WireGuard peer in config after DB error"
+            );
+        }
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+
+    remove_wireguard_peer(&wg_pubkey);
+    remove_peer_route(&vpn_ip);
+
+    if let Ok(ip) = vpn_ip.parse() {
+        state.user_cache.remove(&ip);
+    }
+    state.rules_cache.remove_device(&user_id, &device_id).await;
+    state.logging_prefs.remove_device(user_id, device_id);
+    state.last_seen.remove(&device_id);
+
+    tracing::info!(device_id = %device_id, user_id = %user_id, vpn_ip = %vpn_ip, "Device deprovisioned");
+
+    Ok(RevokedDevice {
+        device_id,
+        user_id,
+        wg_pubkey,
+        vpn_ip,
+    })
+}
+
+/// Remove the host route `add_wireguard_peer` added for a peer
+fn remove_peer_route(vpn_ip: &str) {
+    let result = Command::new("ip")
+        .args(["route", "del", vpn_ip, "dev", "wg0"])
+        .output();
+
+    match result {
+        Ok(output) if output.status.success() => {
+            tracing::info!(vpn_ip = %vpn_ip, "Route removed for peer");
+        }
+        Ok(output) => {
+            tracing::warn!(
+                vpn_ip = %vpn_ip,
+                stderr = %String::from_utf8_lossy(&output.stderr),
+                "Failed to remove route (non-fatal)"
+            );
+        }
+        Err(e) => {
+            tracing::warn!(vpn_ip = %vpn_ip, error = %e, "Failed to execute ip route command (non-fatal)");
+        }
+    }
+}
+
+// List the caller's devices (requires JWT)
+#[derive(Serialize)]
+struct DeviceEntry {
+    device_id: Uuid,
+    device_name: String,
+    wg_pubkey: String,
+    vpn_ip: String,
+    created_at: Option<DateTime<Utc>>,
+    last_dns_at: Option<String>,
+    connected: bool,
+}
+
+type DeviceRow = (Uuid, String, String, String, Option<DateTime<Utc>>);
+
+impl DeviceEntry {
+    fn from_row(state: &AppState, (device_id, device_name, wg_pubkey, vpn_ip, created_at): DeviceRow) -> Self {
+        Self {
+            device_id,
+            device_name,
+            wg_pubkey,
+            vpn_ip,
+            created_at,
+            last_dns_at: state.last_seen.get(&device_id).map(|t| t.to_rfc3339()),
+            connected: state.last_seen.is_connected(&device_id, 5),
+        }
+    }
+}
+
+async fn list_devices(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+) -> Result<Json<Vec<DeviceEntry>>, (StatusCode, String)> {
+    let rows = sqlx::query_as::<_, DeviceRow>(
+        r#"
+        SELECT id, device_name, wg_pubkey, host(vpn_ip), created_at
+        FROM devices
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+    )
+    .bind(claims.user_id)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(rows.into_iter().map(|row| DeviceEntry::from_row(&state, row)).collect()))
+}
+
+// Rename a device (requires JWT, must own device)
+#[derive(Deserialize)]
+struct UpdateDeviceRequest {
+    device_name: String,
+}
+
+async fn update_device(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<UpdateDeviceRequest>,
+) -> Result<Json<DeviceEntry>, (StatusCode, String)> {
+    let device_name = req.device_name.trim();
+    if device_name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "device_name must not be empty".to_string()));
+    }
+    ensure_device_owner(&state, &claims, device_id).await?;
+
+    let row = sqlx::query_as::<_, DeviceRow>(
+        r#"
+        UPDATE devices SET device_name = $2
+        WHERE id = $1
+        RETURNING id, device_name, wg_pubkey, host(vpn_ip), created_at
+        "#,
+    )
+    .bind(device_id)
+    .bind(device_name)
+    .fetch_optional(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Device not found".to_string()))?;
+
+    Ok(Json(DeviceEntry::from_row(&state, row)))
+}
+
+// Delete a device and its WireGuard peer (requires JWT, must own device)
+async fn delete_device(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ensure_device_owner(&state, &claims, device_id).await?;
+    deprovision_device(&state, device_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Replace a device's WireGuard key, keeping its VPN IP (requires JWT, must own device)
+#[derive(Deserialize)]
+struct RotateKeyRequest {
+    wg_pubkey: String,
+}
+
+async fn rotate_device_key(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<RotateKeyRequest>,
+) -> Result<Json<DeviceEntry>, (StatusCode, String)> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let (owner_id, old_pubkey, vpn_ip) = sqlx::query_as::<_, (Uuid, String, String)>(
+        "SELECT user_id, wg_pubkey, host(vpn_ip) FROM devices WHERE id = $1 FOR UPDATE",
+    )
+    .bind(device_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Device not found".to_string()))?;
+
+    if owner_id != claims.user_id {
+        return Err((StatusCode::FORBIDDEN, "Device belongs to another user".to_string()));
+    }
+    if old_pubkey == req.wg_pubkey {
+        return Err((StatusCode::BAD_REQUEST, "Key is unchanged".to_string()));
+    }
+
+    let row = sqlx::query_as::<_, DeviceRow>(
+        r#"
+        UPDATE devices SET wg_pubkey = $2
+        WHERE id = $1
+        RETURNING id, device_name, wg_pubkey, host(vpn_ip), created_at
+        "#,
+    )
+    .bind(device_id)
+    .bind(&req.wg_pubkey)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => {
+            (StatusCode::CONFLICT, "Key already in use".to_string())
+        }
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    })?;
+
+    // The new peer takes over the VPN IP (wg moves allowed-ips between peers)
+    if let Err(e) = add_wireguard_peer(&req.wg_pubkey, &vpn_ip) {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("WireGuard provisioning failed: {}", e),
+        ));
+    }
+
+    if let Err(e) = remove_peer_from_config(&old_pubkey) {
+        revert_key_rotation(&req.wg_pubkey, &old_pubkey, &vpn_ip);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("WireGuard deprovisioning failed: {}", e),
+        ));
+    }
+    remove_wireguard_peer(&old_pubkey);
+
+    if let Err(e) = tx.commit().await {
+        revert_key_rotation(&req.wg_pubkey, &old_pubkey, &vpn_ip);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+    }
+
+    tracing::info!(
+        device_id = %device_id,
+        user_id = %claims.user_id,
+        vpn_ip = %vpn_ip,
+        "Device key rotated"
+    );
+
+    Ok(Json(DeviceEntry::from_row(&state, row)))
+}
+
+/// Undo a half-finished key rotation: drop the new peer, reinstate the old one
+fn revert_key_rotation(new_pubkey: &str, old_pubkey: &str, vpn_ip: &str) {
+    remove_wireguard_peer(new_pubkey);
+    if let Err(e) = remove_peer_from_config(new_pubkey) {
+        tracing::warn!(pubkey = %new_pubkey, error = %e, "Failed to remove WireGuard peer from config");
+    }
+    if let Err(e) = add_wireguard_peer(old_pubkey, vpn_ip) {
+        tracing::error!(pubkey = %old_pubkey, error = %e, "Failed to restore WireGuard peer after key rotation");
+    }
+}
+
 // Get WireGuard config for device (requires JWT, must own device)
 #[derive(Serialize)]
 struct WgConfigResponse {
@@ -700,34 +1044,100 @@ async fn get_device_status(
 #[derive(Serialize)]
 struct StatsResponse {
     queue_length: usize,
+    ingest: crate::ingest::IngestStats,
     cached_users: usize,
+    dns_cache_entries: usize,
+    dns_cache_bytes: usize,
+    dns_cache_hits: u64,
+    dns_cache_misses: u64,
 }
 
 async fn get_stats(State(state): State<Arc<AppState>>) -> Json<StatsResponse> {
     Json(StatsResponse {
-        queue_length: state.tinybird.queue_len().await,
+        queue_length: state.events.queue_len(),
+        ingest: state.events.stats(),
         cached_users: state.user_cache.len(),
+        dns_cache_entries: state.response_cache.len(),
+        dns_cache_bytes: state.response_cache.bytes(),
+        dns_cache_hits: state.response_cache.hits(),
+        dns_cache_misses: state.response_cache.misses(),
     })
 }
 
-// Get blocked domains (requires JWT)
+// Prometheus metrics
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(&state).await,
+    )
+}
+
+// Get rules (requires JWT)
 #[derive(Serialize)]
 struct RulesResponse {
+    /// Suffix block rules, for clients that predate typed rules
     domains: Vec<String>,
+    /// Blocked category slugs (unscheduled category rules)
+    categories: Vec<String>,
+    rules: Vec<Rule>,
+    /// The user's default block response (None = server default)
+    block_mode: Option<BlockMode>,
+}
+
+impl RulesResponse {
+    fn from_rules(rules: Vec<Rule>, block_mode: Option<BlockMode>) -> Self {
+        let plain = |kind: RuleKind| {
+            rules
+                .iter()
+                .filter(|r| r.kind == kind && r.schedule.is_none())
+                .map(|r| r.domain.clone())
+                .collect()
+        };
+        Self {
+            domains: plain(RuleKind::Suffix),
+            categories: plain(RuleKind::Category),
+            rules,
+            block_mode,
+        }
+    }
 }
 
 async fn get_rules(
     State(state): State<Arc<AppState>>,
     AuthUser(claims): AuthUser,
 ) -> Json<RulesResponse> {
-    let domains = state.rules_cache.get_blocked_domains(&claims.user_id).await;
-    Json(RulesResponse { domains })
+    let rules = state.rules_cache.get_rules(&claims.user_id, None).await;
+    let block_mode = state.rules_cache.block_mode(&claims.user_id).await;
+    Json(RulesResponse::from_rules(rules, block_mode))
 }
 
-// Set blocked domains (requires JWT)
+// Set rules (requires JWT)
 #[derive(Deserialize)]
 struct SetRulesRequest {
+    /// Plain domains, treated as suffix block rules
+    #[serde(default)]
     domains: Vec<String>,
+    /// Category slugs to block (e.g. "social")
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    rules: Vec<RuleInput>,
+}
+
+#[derive(Deserialize)]
+struct RuleInput {
+    domain: String,
+    #[serde(default = "default_rule_kind")]
+    kind: RuleKind,
+    #[serde(default)]
+    schedule: Option<Schedule>,
+    /// Overrides the user's default block response for this rule
+    #[serde(default)]
+    block_mode: Option<BlockMode>,
+}
+
+fn default_rule_kind() -> RuleKind {
+    RuleKind::Suffix
 }
 
 async fn set_rules(
@@ -735,31 +1145,106 @@ async fn set_rules(
     AuthUser(claims): AuthUser,
     Json(req): Json<SetRulesRequest>,
 ) -> Result<Json<RulesResponse>, (StatusCode, String)> {
-    // Normalize domains (lowercase, trim)
-    let domains: Vec<String> = req
+    replace_rules(&state, &claims, None, req).await
+}
+
+// Get rules scoped to one device (requires JWT, must own device)
+async fn get_device_rules(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<RulesResponse>, (StatusCode, String)> {
+    ensure_device_owner(&state, &claims, device_id).await?;
+    let rules = state.rules_cache.get_rules(&claims.user_id, Some(device_id)).await;
+    let block_mode = state.rules_cache.block_mode(&claims.user_id).await;
+    Ok(Json(RulesResponse::from_rules(rules, block_mode)))
+}
+
+// Set rules scoped to one device (requires JWT, must own device)
+async fn set_device_rules(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<SetRulesRequest>,
+) -> Result<Json<RulesResponse>, (StatusCode, String)> {
+    ensure_device_owner(&state, &claims, device_id).await?;
+    replace_rules(&state, &claims, Some(device_id), req).await
+}
+
+async fn ensure_device_owner(
+    state: &AppState,
+    claims: &Claims,
+    device_id: Uuid,
+) -> Result<(), (StatusCode, String)> {
+    let owner_id = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM devices WHERE id = $1")
+        .bind(device_id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Device not found".to_string()))?;
+
+    if owner_id != claims.user_id {
+        return Err((StatusCode::FORBIDDEN, "Device belongs to another user".to_string()));
+    }
+    Ok(())
+}
+
+/// Replace the rules in one scope (user-wide when device_id is None)
+async fn replace_rules(
+    state: &AppState,
+    claims: &Claims,
+    device_id: Option<Uuid>,
+    req: SetRulesRequest,
+) -> Result<Json<RulesResponse>, (StatusCode, String)> {
+    // Normalize and validate (lowercase, trim, wildcard placement)
+    let mut rules: Vec<Rule> = Vec::new();
+    let inputs = req
         .domains
-        .iter()
-        .map(|d| d.trim().to_lowercase())
-        .filter(|d| !d.is_empty())
-        .collect();
+        .into_iter()
+        .map(|d| (d, RuleKind::Suffix, None, None))
+        .chain(req.categories.into_iter().map(|c| (c, RuleKind::Category, None, None)))
+        .chain(req.rules.into_iter().map(|r| (r.domain, r.kind, r.schedule, r.block_mode)));
+    for (domain, kind, schedule, block_mode) in inputs {
+        if domain.trim().is_empty() {
+            continue;
+        }
+        let rule = Rule::new(&domain, kind, schedule)
+            .and_then(|r| r.with_block_mode(block_mode))
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        // One rule per domain (matches the table's unique key); later entries win
+        rules.retain(|r| r.domain != rule.domain);
+        rules.push(rule);
+    }
 
-    // Update database (delete all, then insert)
+    // Update database (delete all in scope, then insert)
     let mut tx = state
         .db
         .begin()
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    sqlx::query("DELETE FROM user_rules WHERE user_id = $1")
+    sqlx::query("DELETE FROM user_rules WHERE user_id = $1 AND device_id IS NOT DISTINCT FROM $2")
         .bind(claims.user_id)
+        .bind(device_id)
         .execute(&mut *tx)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    for domain in &domains {
-        sqlx::query("INSERT INTO user_rules (user_id, domain) VALUES ($1, $2) ON CONFLICT (user_id, domain) DO NOTHING")
+    for rule in &rules {
+        let schedule = rule
+            .schedule
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        sqlx::query("INSERT INTO user_rules (user_id, device_id, domain, kind, schedule, block_mode) VALUES ($1, $2, $3, $4, $5::jsonb, $6) ON CONFLICT DO NOTHING")
             .bind(claims.user_id)
-            .bind(domain)
+            .bind(device_id)
+            .bind(&rule.domain)
+            .bind(rule.kind.as_str())
+            .bind(schedule)
+            .bind(rule.block_mode.map(|m| m.as_str()))
             .execute(&mut *tx)
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -770,16 +1255,269 @@ async fn set_rules(
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     // Update cache
-    state.rules_cache.set_blocked_domains(claims.user_id, domains.clone()).await;
+    state.rules_cache.set_rules(claims.user_id, device_id, rules.clone()).await;
 
     tracing::info!(
         user_id = %claims.user_id,
         wallet = %claims.sub,
-        count = domains.len(),
+        device_id = ?device_id,
+        count = rules.len(),
         "Rules updated"
     );
 
-    Ok(Json(RulesResponse { domains }))
+    let block_mode = state.rules_cache.block_mode(&claims.user_id).await;
+    Ok(Json(RulesResponse::from_rules(rules, block_mode)))
+}
+
+// Set the default block response for all of a user's rules (requires JWT)
+#[derive(Deserialize, Serialize)]
+struct BlockModeRequest {
+    /// None resets to the server default
+    block_mode: Option<BlockMode>,
+}
+
+async fn set_block_mode(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<BlockModeRequest>,
+) -> Result<Json<BlockModeRequest>, (StatusCode, String)> {
+    sqlx::query("UPDATE users SET block_mode = $1 WHERE id = $2")
+        .bind(req.block_mode.map(|m| m.as_str()))
+        .bind(claims.user_id)
+        .execute(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    state.rules_cache.set_block_mode(claims.user_id, req.block_mode).await;
+
+    tracing::info!(
+        user_id = %claims.user_id,
+        block_mode = ?req.block_mode,
+        "Block mode updated"
+    );
+
+    Ok(Json(req))
+}
+
+// Interest logging preferences (requires JWT)
+#[derive(Serialize)]
+struct LoggingResponse {
+    #[serde(flatten)]
+    settings: LoggingSettings,
+    /// Mode in force right now (accounts for pauses and, for devices, the user setting)
+    effective: LoggingMode,
+}
+
+#[derive(Deserialize)]
+struct SetLoggingRequest {
+    mode: LoggingMode,
+    /// Pause all logging until this time
+    #[serde(default)]
+    paused_until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Shorthand for paused_until = now + minutes
+    #[serde(default)]
+    pause_minutes: Option<i64>,
+}
+
+async fn get_logging(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+) -> Json<LoggingResponse> {
+    Json(logging_response(&state, claims.user_id, None))
+}
+
+async fn set_logging(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<SetLoggingRequest>,
+) -> Result<Json<LoggingResponse>, (StatusCode, String)> {
+    update_logging(&state, &claims, None, req).await
+}
+
+// Logging preferences for one device (requires JWT, must own device)
+async fn get_device_logging(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+) -> Result<Json<LoggingResponse>, (StatusCode, String)> {
+    ensure_device_owner(&state, &claims, device_id).await?;
+    Ok(Json(logging_response(&state, claims.user_id, Some(device_id))))
+}
+
+async fn set_device_logging(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Path(device_id): Path<Uuid>,
+    Json(req): Json<SetLoggingRequest>,
+) -> Result<Json<LoggingResponse>, (StatusCode, String)> {
+    ensure_device_owner(&state, &claims, device_id).await?;
+    update_logging(&state, &claims, Some(device_id), req).await
+}
+
+fn logging_response(state: &AppState, user_id: Uuid, device_id: Option<Uuid>) -> LoggingResponse {
+    LoggingResponse {
+        settings: state.logging_prefs.get(user_id, device_id),
+        effective: state
+            .logging_prefs
+            .effective(user_id, device_id.unwrap_or_else(Uuid::nil)),
+    }
+}
+
+/// Persist and cache logging settings for a user (device_id None) or device
+async fn update_logging(
+    state: &AppState,
+    claims: &Claims,
+    device_id: Option<Uuid>,
+    req: SetLoggingRequest,
+) -> Result<Json<LoggingResponse>, (StatusCode, String)> {
+    let paused_until = match (req.paused_until, req.pause_minutes) {
+        (Some(_), Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Set paused_until or pause_minutes, not both".to_string(),
+            ))
+        }
+        (_, Some(m)) if m <= 0 || m > MAX_PAUSE_MINUTES => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("pause_minutes must be between 1 and {}", MAX_PAUSE_MINUTES),
+            ))
+        }
+        (_, Some(m)) => chrono::TimeDelta::try_minutes(m)
+            .and_then(|d| chrono::Utc::now().checked_add_signed(d)),
+        (until, None) => until,
+    };
+    let max_until = chrono::TimeDelta::try_minutes(MAX_PAUSE_MINUTES)
+        .and_then(|d| chrono::Utc::now().checked_add_signed(d));
+    if paused_until.is_some_and(|until| max_until.is_none_or(|max| until > max)) {
+        return Err((StatusCode::BAD_REQUEST, "Logging can be paused for at most a year".to_string()));
+    }
+
+    let query = match device_id {
+        None => "UPDATE users SET logging_mode = $1, logging_paused_until = $2 WHERE id = $3",
+        Some(_) => "UPDATE devices SET logging_mode = $1, logging_paused_until = $2 WHERE id = $3",
+    };
+    sqlx::query(query)
+        .bind(req.mode.as_str())
+        .bind(paused_until)
+        .bind(device_id.unwrap_or(claims.user_id))
+        .execute(&state.db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let settings = LoggingSettings {
+        mode: req.mode,
+        paused_until,
+    };
+    state.logging_prefs.set(claims.user_id, device_id, settings);
+
+    tracing::info!(
+        user_id = %claims.user_id,
+        device_id = ?device_id,
+        mode = req.mode.as_str(),
+        paused_until = ?paused_until,
+        "Logging preferences updated"
+    );
+
+    Ok(Json(logging_response(state, claims.user_id, device_id)))
+}
+
+// List available blocklists with the caller's subscription state (requires JWT)
+#[derive(Serialize)]
+struct BlocklistEntry {
+    id: String,
+    domains: usize,
+    updated_at: Option<String>,
+    subscribed: bool,
+}
+
+#[derive(Serialize)]
+struct BlocklistsResponse {
+    lists: Vec<BlocklistEntry>,
+}
+
+async fn get_blocklists(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+) -> Json<BlocklistsResponse> {
+    let subscribed = state.blocklists.get_subscriptions(&claims.user_id).await;
+    let lists = state
+        .blocklists
+        .list_info()
+        .await
+        .into_iter()
+        .map(|l| BlocklistEntry {
+            subscribed: subscribed.contains(&l.id),
+            id: l.id,
+            domains: l.domains,
+            updated_at: l.updated_at.map(|t| t.to_rfc3339()),
+        })
+        .collect();
+    Json(BlocklistsResponse { lists })
+}
+
+// Set blocklist subscriptions (requires JWT, replaces existing)
+#[derive(Deserialize)]
+struct SetSubscriptionsRequest {
+    list_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SubscriptionsResponse {
+    list_ids: Vec<String>,
+}
+
+async fn set_blocklist_subscriptions(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    Json(req): Json<SetSubscriptionsRequest>,
+) -> Result<Json<SubscriptionsResponse>, (StatusCode, String)> {
+    let list_ids: std::collections::HashSet<String> =
+        req.list_ids.iter().map(|id| id.trim().to_string()).collect();
+
+    if let Some(unknown) = list_ids.iter().find(|id| !state.blocklists.has_list(id)) {
+        return Err((StatusCode::BAD_REQUEST, format!("Unknown blocklist: {}", unknown)));
+    }
+
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    sqlx::query("DELETE FROM user_blocklists WHERE user_id = $1")
+        .bind(claims.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    for list_id in &list_ids {
+        sqlx::query("INSERT INTO user_blocklists (user_id, list_id) VALUES ($1, $2)")
+            .bind(claims.user_id)
+            .bind(list_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    state
+        .blocklists
+        .set_subscriptions(claims.user_id, list_ids.clone())
+        .await;
+
+    tracing::info!(
+        user_id = %claims.user_id,
+        count = list_ids.len(),
+        "Blocklist subscriptions updated"
+    );
+
+    let mut list_ids: Vec<String> = list_ids.into_iter().collect();
+    list_ids.sort();
+    Ok(Json(SubscriptionsResponse { list_ids }))
 }
 
 fn parse_vpn_subnet(subnet: &str) -> Result<[u8; 3], (StatusCode, String)> {
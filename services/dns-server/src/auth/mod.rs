@@ -118,12 +118,12 @@ impl AuthState {
             .parse()
             .map_err(|e| AuthError::InvalidMessage(format!("{:?}", e)))?;
 
-        if parsed.domain.to_string() != self.domain {
+        if parsed.domain != self.domain.as_str() {
             return Err(AuthError::DomainMismatch);
         }
 
         let expected_uri = format!("https://{}", self.domain);
-        if parsed.uri.to_string() != expected_uri {
+        if parsed.uri != expected_uri.as_str() {
             return Err(AuthError::UriMismatch);
         }
 
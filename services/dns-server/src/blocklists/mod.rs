@@ -0,0 +1,397 @@
+//! Subscribable community blocklists
+//!
+//! Lists are loaded from local files or URLs and refreshed periodically.
+//! Each list is parsed into a reversed-label trie shared by every subscriber,
+//! so memory is paid once per list rather than once per user. Users opt in
+//! by list ID (`user_blocklists`), alongside their own `user_rules`.
+//!
+//! Supported line formats (detected per line, so mixed files work):
+//! - hosts: `0.0.0.0 ads.example.com` (several names per line allowed)
+//! - AdGuard/ABP: `||ads.example.com^` (lines with `$` modifiers are skipped)
+//! - plain domain list: `ads.example.com`
+//!
+//! Every entry blocks the domain and all its subdomains.
+
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use uuid::Uuid;
+
+/// Hostnames that hosts files map for local use, never worth blocking
+const HOSTS_IGNORED: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "0.0.0.0",
+];
+
+/// Largest list accepted, so one hostile source can't exhaust memory
+const MAX_LIST_BYTES: usize = 64 * 1024 * 1024;
+/// Time allowed to connect to a list URL
+const FETCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed for a whole list download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Reversed-label trie: `ads.example.com` is stored as com -> example -> ads
+#[derive(Default)]
+pub struct DomainTrie {
+    root: TrieNode,
+    len: usize,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<Box<str>, TrieNode>,
+    /// A listed domain ends here (blocks it and everything below)
+    terminal: bool,
+}
+
+impl DomainTrie {
+    /// Insert a domain; returns false if it was already covered
+    pub fn insert(&mut self, domain: &str) -> bool {
+        let mut node = &mut self.root;
+        for label in domain.rsplit('.') {
+            if node.terminal {
+                // A parent is already listed; this entry adds nothing
+                return false;
+            }
+            node = node.children.entry(label.into()).or_default();
+        }
+        if node.terminal {
+            return false;
+        }
+        node.terminal = true;
+        // Children are now redundant, and so are the entries below them
+        let covered = node.terminals();
+        node.children = HashMap::new();
+        self.len = self.len + 1 - covered;
+        true
+    }
+
+    /// True if `domain` or any of its parents is listed
+    pub fn matches(&self, domain: &str) -> bool {
+        let mut node = &self.root;
+        for label in domain.rsplit('.') {
+            match node.children.get(label) {
+                Some(child) if child.terminal => return true,
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl TrieNode {
+    /// Listed domains in this node's subtree, excluding the node itself
+    fn terminals(&self) -> usize {
+        self.children
+            .values()
+            .map(|c| usize::from(c.terminal) + c.terminals())
+            .sum()
+    }
+}
+
+/// A configured list source
+#[derive(Debug, Clone)]
+pub struct BlocklistSource {
+    pub id: String,
+    /// `http(s)://` URL or local file path
+    pub location: String,
+}
+
+/// A loaded list
+pub struct Blocklist {
+    pub trie: DomainTrie,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Summary of a list for the API
+pub struct BlocklistInfo {
+    pub id: String,
+    pub location: String,
+    pub domains: usize,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Loaded lists plus per-user subscriptions
+pub struct BlocklistManager {
+    sources: Vec<BlocklistSource>,
+    http: reqwest::Client,
+    /// list_id -> loaded list (swapped wholesale on refresh)
+    lists: RwLock<HashMap<String, Arc<Blocklist>>>,
+    /// user_id -> subscribed list IDs
+    subscriptions: RwLock<HashMap<Uuid, HashSet<String>>>,
+}
+
+impl BlocklistManager {
+    pub fn new(sources: Vec<BlocklistSource>) -> Self {
+        Self {
+            sources,
+            http: reqwest::Client::builder()
+                .connect_timeout(FETCH_CONNECT_TIMEOUT)
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            lists: RwLock::new(HashMap::new()),
+            subscriptions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a list ID is configured
+    pub fn has_list(&self, id: &str) -> bool {
+        self.sources.iter().any(|s| s.id == id)
+    }
+
+    /// Configured lists with their current load state
+    pub async fn list_info(&self) -> Vec<BlocklistInfo> {
+        let lists = self.lists.read().await;
+        self.sources
+            .iter()
+            .map(|s| {
+                let loaded = lists.get(&s.id);
+                BlocklistInfo {
+                    id: s.id.clone(),
+                    location: s.location.clone(),
+                    domains: loaded.map(|l| l.trie.len()).unwrap_or(0),
+                    updated_at: loaded.map(|l| l.updated_at),
+                }
+            })
+            .collect()
+    }
+
+    /// Load subscriptions from database at startup
+    pub async fn load_subscriptions(&self, db: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT user_id, list_id FROM user_blocklists"
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut subs = self.subscriptions.write().await;
+        subs.clear();
+        for (user_id, list_id) in rows {
+            subs.entry(user_id).or_default().insert(list_id);
+        }
+
+        tracing::info!(users = subs.len(), "Blocklist subscriptions loaded");
+        Ok(())
+    }
+
+    /// Get a user's subscribed list IDs
+    pub async fn get_subscriptions(&self, user_id: &Uuid) -> HashSet<String> {
+        let subs = self.subscriptions.read().await;
+        subs.get(user_id).cloned().unwrap_or_default()
+    }
+
+    /// Set a user's subscriptions (replaces existing)
+    pub async fn set_subscriptions(&self, user_id: Uuid, list_ids: HashSet<String>) {
+        self.subscriptions.write().await.insert(user_id, list_ids);
+    }
+
+    /// Check if a domain is blocked by any list the user subscribes to
+    pub async fn is_blocked(&self, user_id: &Uuid, domain: &str) -> bool {
+        let subs = self.subscriptions.read().await;
+        let Some(ids) = subs.get(user_id).filter(|ids| !ids.is_empty()) else {
+            return false;
+        };
+
+        let lists = self.lists.read().await;
+        ids.iter()
+            .filter_map(|id| lists.get(id))
+            .any(|l| l.trie.matches(domain))
+    }
+
+    /// Fetch and re-parse every configured list, keeping the old copy on failure
+    pub async fn refresh_all(&self) {
+        for source in &self.sources {
+            match self.fetch(source).await {
+                Ok(text) => {
+                    let trie = parse_list(&text);
+                    tracing::info!(list = %source.id, domains = trie.len(), "Blocklist loaded");
+                    self.lists.write().await.insert(
+                        source.id.clone(),
+                        Arc::new(Blocklist {
+                            trie,
+                            updated_at: Utc::now(),
+                        }),
+                    );
+                }
+                Err(e) => {
+                    tracing::warn!(list = %source.id, error = %e, "Blocklist refresh failed");
+                }
+            }
+        }
+    }
+
+    async fn fetch(&self, source: &BlocklistSource) -> anyhow::Result<String> {
+        if source.location.starts_with("http://") || source.location.starts_with("https://") {
+            let mut resp = self.http.get(&source.location).send().await?.error_for_status()?;
+            if resp.content_length().is_some_and(|n| n > MAX_LIST_BYTES as u64) {
+                anyhow::bail!("List exceeds {} bytes", MAX_LIST_BYTES);
+            }
+            // Stream with a cap: Content-Length may be absent or wrong
+            let mut body = Vec::new();
+            while let Some(chunk) = resp.chunk().await? {
+                if body.len() + chunk.len() > MAX_LIST_BYTES {
+                    anyhow::bail!("List exceeds {} bytes", MAX_LIST_BYTES);
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(String::from_utf8_lossy(&body).into_owned())
+        } else {
+            let size = tokio::fs::metadata(&source.location).await?.len();
+            if size > MAX_LIST_BYTES as u64 {
+                anyhow::bail!("List exceeds {} bytes", MAX_LIST_BYTES);
+            }
+            Ok(tokio::fs::read_to_string(&source.location).await?)
+        }
+    }
+}
+
+/// Parse BLOCKLISTS config: comma-separated `id=location` pairs
+pub fn parse_sources(s: &str) -> anyhow::Result<Vec<BlocklistSource>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|entry| {
+            let (id, location) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid blocklist entry (want id=location): {}", entry))?;
+            Ok(BlocklistSource {
+                id: id.trim().to_string(),
+                location: location.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse a list in hosts, AdGuard/ABP or plain-domain format
+pub fn parse_list(text: &str) -> DomainTrie {
+    let mut trie = DomainTrie::default();
+    for line in text.lines() {
+        for domain in parse_line(line) {
+            trie.insert(&domain);
+        }
+    }
+    trie
+}
+
+fn parse_line(line: &str) -> Vec<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['#', '!', '[']) || line.starts_with("@@") {
+        return vec![];
+    }
+
+    // AdGuard/ABP: ||domain^ (modifiers other than a bare ^ don't apply to DNS)
+    if let Some(rest) = line.strip_prefix("||") {
+        if line.contains('$') {
+            return vec![];
+        }
+        let domain = rest.trim_end_matches('^').trim_end_matches('|');
+        return normalize(domain).into_iter().collect();
+    }
+
+    // Strip trailing comments
+    let line = line.split('#').next().unwrap_or("").trim();
+    let mut tokens = line.split_whitespace();
+    let Some(first) = tokens.next() else {
+        return vec![];
+    };
+
+    // hosts: <ip> <name> [name...]
+    if first.parse::<IpAddr>().is_ok() {
+        return tokens
+            .filter(|t| !HOSTS_IGNORED.contains(t))
+            .filter_map(normalize)
+            .collect();
+    }
+
+    // Plain domain list
+    normalize(first).into_iter().collect()
+}
+
+fn normalize(domain: &str) -> Option<String> {
+    let d = domain.trim().trim_end_matches('.').to_lowercase();
+    let valid = !d.is_empty()
+        && d.contains('.')
+        && d.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_');
+    valid.then_some(d)
+}
+
+/// Background task that periodically refreshes all lists
+pub async fn refresh_loop(state: Arc<crate::AppState>, mut shutdown: broadcast::Receiver<()>) {
+    let interval = tokio::time::Duration::from_secs(state.config.blocklist_refresh_secs.max(60));
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.recv() => {
+                tracing::info!("Blocklist refresher stopped");
+                break;
+            }
+            // First tick fires immediately, doing the initial load
+            _ = ticker.tick() => {
+                state.blocklists.refresh_all().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let trie = parse_list(
+            "# hosts\n\
+             0.0.0.0 ads.example.com tracker.example.net # inline\n\
+             127.0.0.1 localhost\n\
+             ! adblock\n\
+             ||doubleclick.net^\n\
+             ||cdn.example.org^$third-party\n\
+             @@||allowed.com^\n\
+             plain.example.io\n",
+        );
+        assert_eq!(trie.len(), 4);
+        assert!(trie.matches("ads.example.com"));
+        assert!(trie.matches("x.tracker.example.net"));
+        assert!(trie.matches("stats.g.doubleclick.net"));
+        assert!(trie.matches("plain.example.io"));
+        assert!(!trie.matches("example.com"));
+        assert!(!trie.matches("cdn.example.org"));
+        assert!(!trie.matches("localhost"));
+        assert!(!trie.matches("allowed.com"));
+    }
+
+    #[test]
+    fn test_trie_parent_covers_children() {
+        let mut trie = DomainTrie::default();
+        assert!(trie.insert("a.example.com"));
+        assert!(trie.insert("x.y.example.com"));
+        assert!(trie.insert("example.com"));
+        assert!(!trie.insert("b.example.com"));
+        assert_eq!(trie.len(), 1);
+        assert!(trie.matches("a.example.com"));
+        assert!(!trie.matches("com"));
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let sources = parse_sources("ads=https://example.com/hosts, local=/etc/block.txt").unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[1].id, "local");
+        assert!(parse_sources("nope").is_err());
+    }
+}
@@ -1,11 +1,80 @@
 //! Domain categorization for interest matching
+//!
+//! The domain -> category map is data, not code: a versioned TOML file
+//! (`data/categories.toml`, embedded at build time) that operators can
+//! replace via CATEGORY_MAP_PATH and reload with SIGHUP. Every entry must
+//! name a known `Category`; an invalid file is rejected as a whole.
+//!
+//! A domain maps to a weighted set of categories (weights sum to 1), since
+//! sites like youtube.com span several interests. Plain entries carry a
+//! single category at weight 1.
+//!
+//! Separately, `is_infra` recognizes CDN, analytics and OS telemetry hosts
+//! (listed in `[infra]`, or flagged by their leftmost labels) so they aren't
+//! logged as interest signal; see `InfraPolicy`.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast;
+
+/// Category map file format version this build writes; older files are
+/// still accepted (version 1: no `[weighted]`, version 2: no `[infra]`)
+const SCHEMA_VERSION: u32 = 3;
+
+/// Hex/digit labels at least this long look machine-generated (edge node ids)
+const HASHED_LABEL_MIN_LEN: usize = 16;
+
+/// Built-in map, used when CATEGORY_MAP_PATH is unset
+const DEFAULT_MAP: &str = include_str!("../../data/categories.toml");
+
+/// Domain to category mapping, swappable at runtime
+pub struct CategoryMap {
+    /// Source file (None = embedded default)
+    path: Option<String>,
+    data: RwLock<Arc<CategoryData>>,
+}
 
-use std::collections::HashMap;
+struct CategoryData {
+    version: u32,
+    exact: HashMap<String, Vec<CategoryWeight>>,
+    suffix: HashMap<String, Vec<CategoryWeight>>,
+    /// Infrastructure domains
+    infra: HashSet<String>,
+    /// Leftmost labels that mark a host as infrastructure
+    infra_labels: HashSet<String>,
+}
 
-/// Domain to category mapping
-pub struct CategoryMap {
-    exact: HashMap<String, i32>,
-    suffix: Vec<(String, i32)>,
+/// What happens to events for infrastructure domains
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InfraPolicy {
+    /// Log with `infra` set and no categories
+    Tag,
+    /// Don't log at all
+    Drop,
+}
+
+/// One category of a domain with its share of the domain's interest signal
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CategoryWeight {
+    pub category_id: i32,
+    pub weight: f32,
+}
+
+impl CategoryWeight {
+    fn sole(category_id: i32) -> Vec<Self> {
+        vec![Self { category_id, weight: 1.0 }]
+    }
+}
+
+/// The highest-weighted category (what category block rules match against)
+pub fn primary_category(categories: &[CategoryWeight]) -> Option<i32> {
+    categories
+        .iter()
+        .max_by(|a, b| a.weight.total_cmp(&b.weight))
+        .map(|c| c.category_id)
 }
 
 /// Interest categories for dating
@@ -43,151 +112,348 @@ pub enum Category {
     Politics = 103,
 }
 
-impl CategoryMap {
-    /// Load category mappings (hardcoded for now, could load from DB/file)
-    pub fn load() -> anyhow::Result<Self> {
-        let mut exact = HashMap::new();
-        let mut suffix = Vec::new();
-
-        // Gaming
-        for domain in ["steam.com", "steampowered.com", "epicgames.com", "twitch.tv",
-                       "discord.com", "riotgames.com", "blizzard.com", "ea.com",
-                       "xbox.com", "playstation.com", "nintendo.com", "itch.io"] {
-            exact.insert(domain.to_string(), Category::Gaming as i32);
-        }
-
-        // Music
-        for domain in ["spotify.com", "music.apple.com", "soundcloud.com", "bandcamp.com",
-                       "last.fm", "genius.com", "shazam.com", "deezer.com"] {
-            exact.insert(domain.to_string(), Category::Music as i32);
-        }
-
-        // Fitness
-        for domain in ["strava.com", "myfitnesspal.com", "nike.com", "underarmour.com",
-                       "peloton.com", "fitbit.com", "garmin.com"] {
-            exact.insert(domain.to_string(), Category::Fitness as i32);
-        }
-
-        // Streaming / Movies
-        for domain in ["netflix.com", "hulu.com", "disneyplus.com", "hbomax.com",
-                       "primevideo.com", "imdb.com", "rottentomatoes.com", "letterboxd.com"] {
-            exact.insert(domain.to_string(), Category::Streaming as i32);
-        }
-
-        // Anime
-        for domain in ["crunchyroll.com", "funimation.com", "myanimelist.net",
-                       "anilist.co", "vrv.co"] {
-            exact.insert(domain.to_string(), Category::Anime as i32);
+impl Category {
+    pub const ALL: [Category; 28] = [
+        Category::Unknown,
+        Category::Gaming,
+        Category::Fitness,
+        Category::Music,
+        Category::Movies,
+        Category::Anime,
+        Category::Cooking,
+        Category::Travel,
+        Category::Outdoors,
+        Category::Tech,
+        Category::Programming,
+        Category::Finance,
+        Category::Fashion,
+        Category::Art,
+        Category::Reading,
+        Category::Podcasts,
+        Category::Sports,
+        Category::Pets,
+        Category::Diy,
+        Category::Photography,
+        Category::News,
+        Category::Social,
+        Category::Shopping,
+        Category::Streaming,
+        Category::Health,
+        Category::Adult,
+        Category::Religion,
+        Category::Politics,
+    ];
+
+    /// Stable lowercase name used in the API and data files
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Category::Unknown => "unknown",
+            Category::Gaming => "gaming",
+            Category::Fitness => "fitness",
+            Category::Music => "music",
+            Category::Movies => "movies",
+            Category::Anime => "anime",
+            Category::Cooking => "cooking",
+            Category::Travel => "travel",
+            Category::Outdoors => "outdoors",
+            Category::Tech => "tech",
+            Category::Programming => "programming",
+            Category::Finance => "finance",
+            Category::Fashion => "fashion",
+            Category::Art => "art",
+            Category::Reading => "reading",
+            Category::Podcasts => "podcasts",
+            Category::Sports => "sports",
+            Category::Pets => "pets",
+            Category::Diy => "diy",
+            Category::Photography => "photography",
+            Category::News => "news",
+            Category::Social => "social",
+            Category::Shopping => "shopping",
+            Category::Streaming => "streaming",
+            Category::Health => "health",
+            Category::Adult => "adult",
+            Category::Religion => "religion",
+            Category::Politics => "politics",
         }
+    }
 
-        // Tech / Programming
-        for domain in ["github.com", "stackoverflow.com", "gitlab.com", "bitbucket.org",
-                       "hackernews.com", "news.ycombinator.com", "dev.to", "medium.com",
-                       "techcrunch.com", "theverge.com", "arstechnica.com", "wired.com"] {
-            exact.insert(domain.to_string(), Category::Tech as i32);
-        }
+    pub fn from_slug(s: &str) -> Option<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|c| c.slug() == s)
+    }
 
-        // Social
-        for domain in ["twitter.com", "x.com", "facebook.com", "instagram.com",
-                       "reddit.com", "tiktok.com", "snapchat.com", "linkedin.com"] {
-            exact.insert(domain.to_string(), Category::Social as i32);
-        }
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| *c as i32 == id)
+    }
+}
 
-        // Shopping
-        for domain in ["amazon.com", "ebay.com", "etsy.com", "shopify.com",
-                       "aliexpress.com", "walmart.com", "target.com"] {
-            exact.insert(domain.to_string(), Category::Shopping as i32);
-        }
+impl CategoryMap {
+    /// Load the map from `path`, or the embedded default when None
+    pub fn load(path: Option<&str>) -> anyhow::Result<Self> {
+        let data = read_map(path)?;
+        tracing::info!(
+            version = data.version,
+            exact = data.exact.len(),
+            suffix = data.suffix.len(),
+            "Category map loaded"
+        );
+        Ok(Self {
+            path: path.map(str::to_string),
+            data: RwLock::new(Arc::new(data)),
+        })
+    }
 
-        // News
-        for domain in ["nytimes.com", "washingtonpost.com", "bbc.com", "cnn.com",
-                       "reuters.com", "apnews.com", "theguardian.com"] {
-            exact.insert(domain.to_string(), Category::News as i32);
-        }
+    /// Re-read the map from its source, keeping the current one if the new
+    /// file is invalid. Returns the number of exact mappings now loaded.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let data = read_map(self.path.as_deref())?;
+        let count = data.exact.len();
+        tracing::info!(
+            version = data.version,
+            exact = count,
+            suffix = data.suffix.len(),
+            "Category map reloaded"
+        );
+        *self.data.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(data);
+        Ok(count)
+    }
 
-        // Travel
-        for domain in ["airbnb.com", "booking.com", "expedia.com", "tripadvisor.com",
-                       "kayak.com", "hotels.com", "vrbo.com"] {
-            exact.insert(domain.to_string(), Category::Travel as i32);
+    /// Lookup categories for a full query name (lowercase, no trailing dot),
+    /// heaviest first; empty if uncategorized. Walks from the full name
+    /// towards the TLD and returns the longest match, so `music.apple.com`
+    /// wins over `apple.com`.
+    pub fn lookup(&self, domain: &str) -> Vec<CategoryWeight> {
+        let data = self.data.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut rest = domain;
+        loop {
+            if let Some(cats) = data.exact.get(rest).or_else(|| data.suffix.get(rest)) {
+                return cats.clone();
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return Vec::new(),
+            }
         }
+    }
 
-        // Finance
-        for domain in ["robinhood.com", "coinbase.com", "binance.com", "kraken.com",
-                       "fidelity.com", "schwab.com", "vanguard.com", "mint.com"] {
-            exact.insert(domain.to_string(), Category::Finance as i32);
-        }
+    /// Whether a full query name is infrastructure (CDN, analytics, telemetry)
+    /// rather than something the user chose to visit. A listed infra domain
+    /// counts unless a longer name is categorized; otherwise the leftmost
+    /// label, if it is left of the longest categorized name (or of the
+    /// eTLD+1), is checked against `[infra] labels` and for machine-generated
+    /// ids. Deeper labels are not checked, so `www.stats.example.com` is a
+    /// destination.
+    pub fn is_infra(&self, domain: &str) -> bool {
+        let data = self.data.read().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut rest = domain;
+        let matched = loop {
+            if data.infra.contains(rest) {
+                return true;
+            }
+            if data.exact.contains_key(rest) || data.suffix.contains_key(rest) {
+                break Some(rest);
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => break None,
+            }
+        };
+
+        let base = matched.or_else(|| psl::domain_str(domain)).unwrap_or(domain);
+        let Some(host) = domain.strip_suffix(base).and_then(|h| h.strip_suffix('.')) else {
+            return false;
+        };
+        let leftmost = host.split('.').next().unwrap_or(host);
+        is_infra_label(leftmost, &data.infra_labels)
+    }
+}
 
-        // Cooking
-        for domain in ["allrecipes.com", "foodnetwork.com", "epicurious.com",
-                       "seriouseats.com", "bonappetit.com", "tasty.co"] {
-            exact.insert(domain.to_string(), Category::Cooking as i32);
-        }
+/// `label` is a listed infra label (also as `cdn-eu`, `cdn2`, `img-cdn`) or
+/// looks machine-generated
+fn is_infra_label(label: &str, infra_labels: &HashSet<String>) -> bool {
+    if infra_labels.contains(label) {
+        return true;
+    }
+    let hashed = label.len() >= HASHED_LABEL_MIN_LEN
+        && label.bytes().all(|b| b.is_ascii_hexdigit() || b == b'-')
+        && label.bytes().any(|b| b.is_ascii_digit());
+    if hashed {
+        return true;
+    }
+    let word = label.trim_end_matches(|c: char| c.is_ascii_digit());
+    infra_labels.contains(word)
+        || label.split_once('-').is_some_and(|(head, _)| infra_labels.contains(head))
+        || label.rsplit_once('-').is_some_and(|(_, tail)| infra_labels.contains(tail))
+}
 
-        // Reading
-        for domain in ["goodreads.com", "kindle.amazon.com", "audible.com",
-                       "scribd.com", "librarything.com"] {
-            exact.insert(domain.to_string(), Category::Reading as i32);
-        }
+/// On-disk layout of a category map file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CategoryFile {
+    version: u32,
+    /// slug -> domains
+    #[serde(default)]
+    exact: BTreeMap<String, Vec<String>>,
+    /// slug -> domain suffixes
+    #[serde(default)]
+    suffix: BTreeMap<String, Vec<String>>,
+    /// domain -> { slug = weight } for domains spanning several categories
+    #[serde(default)]
+    weighted: BTreeMap<String, BTreeMap<String, f32>>,
+    #[serde(default)]
+    infra: InfraFile,
+}
 
-        // Podcasts
-        for domain in ["podcasts.apple.com", "pocketcasts.com", "overcast.fm",
-                       "castbox.fm", "anchor.fm"] {
-            exact.insert(domain.to_string(), Category::Podcasts as i32);
-        }
+/// `[infra]` table of a category map file
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InfraFile {
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
 
-        // Sports
-        for domain in ["espn.com", "nba.com", "nfl.com", "mlb.com",
-                       "fifa.com", "uefa.com", "bleacherreport.com"] {
-            exact.insert(domain.to_string(), Category::Sports as i32);
+fn read_map(path: Option<&str>) -> anyhow::Result<CategoryData> {
+    match path {
+        Some(p) => {
+            let text = std::fs::read_to_string(p)
+                .with_context(|| format!("Failed to read category map {}", p))?;
+            parse_map(&text).with_context(|| format!("Invalid category map {}", p))
         }
+        None => parse_map(DEFAULT_MAP).context("Invalid embedded category map"),
+    }
+}
 
-        // Photography
-        for domain in ["flickr.com", "500px.com", "unsplash.com", "pexels.com",
-                       "adobe.com", "lightroom.adobe.com"] {
-            exact.insert(domain.to_string(), Category::Photography as i32);
+/// Parse and validate a category map file
+fn parse_map(text: &str) -> anyhow::Result<CategoryData> {
+    let file: CategoryFile = toml::from_str(text)?;
+    anyhow::ensure!(
+        (1..=SCHEMA_VERSION).contains(&file.version),
+        "Unsupported category map version {} (expected 1-{})",
+        file.version,
+        SCHEMA_VERSION
+    );
+    anyhow::ensure!(
+        file.version >= 2 || file.weighted.is_empty(),
+        "[weighted] requires version 2"
+    );
+    anyhow::ensure!(
+        file.version >= 3 || (file.infra.domains.is_empty() && file.infra.labels.is_empty()),
+        "[infra] requires version 3"
+    );
+
+    let mut exact = HashMap::new();
+    for (slug, domains) in &file.exact {
+        let id = category_id(slug)?;
+        for domain in domains {
+            let domain = normalize_entry(domain)?;
+            if let Some(prev) = exact.insert(domain.clone(), CategoryWeight::sole(id)) {
+                anyhow::ensure!(prev[0].category_id == id, "{} is listed under more than one category", domain);
+            }
         }
+    }
 
-        // Art
-        for domain in ["deviantart.com", "artstation.com", "behance.net",
-                       "dribbble.com", "pinterest.com"] {
-            exact.insert(domain.to_string(), Category::Art as i32);
+    for (domain, weights) in &file.weighted {
+        let domain = normalize_entry(domain)?;
+        let mut cats = weights
+            .iter()
+            .map(|(slug, &weight)| {
+                anyhow::ensure!(weight > 0.0 && weight.is_finite(), "{}: weight for {} must be positive", domain, slug);
+                Ok(CategoryWeight { category_id: category_id(slug)?, weight })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(!cats.is_empty(), "{} has no categories", domain);
+
+        // Normalize so weights sum to 1, heaviest first
+        let total: f32 = cats.iter().map(|c| c.weight).sum();
+        for c in &mut cats {
+            c.weight /= total;
         }
+        cats.sort_by(|a, b| b.weight.total_cmp(&a.weight));
 
-        // Pets
-        for domain in ["chewy.com", "petco.com", "petsmart.com", "akc.org"] {
-            exact.insert(domain.to_string(), Category::Pets as i32);
-        }
+        anyhow::ensure!(
+            exact.insert(domain.clone(), cats).is_none(),
+            "{} is listed under more than one category",
+            domain
+        );
+    }
 
-        // DIY
-        for domain in ["instructables.com", "hackaday.com", "makezine.com",
-                       "homedepot.com", "lowes.com"] {
-            exact.insert(domain.to_string(), Category::Diy as i32);
+    let mut suffix = HashMap::new();
+    for (slug, suffixes) in &file.suffix {
+        let id = category_id(slug)?;
+        for sfx in suffixes {
+            suffix.insert(normalize_entry(sfx.trim_start_matches('.'))?, CategoryWeight::sole(id));
         }
+    }
 
-        // Suffix-based rules (less common)
-        suffix.push((".edu".to_string(), Category::Reading as i32));
-        suffix.push((".gov".to_string(), Category::News as i32));
-
-        tracing::info!("Loaded {} exact domain mappings", exact.len());
+    let mut infra = HashSet::new();
+    for domain in &file.infra.domains {
+        let domain = normalize_entry(domain)?;
+        anyhow::ensure!(!exact.contains_key(&domain), "{} is listed as both infra and a category", domain);
+        infra.insert(domain);
+    }
+    let infra_labels = file
+        .infra
+        .labels
+        .iter()
+        .map(|l| {
+            let l = l.trim().to_ascii_lowercase();
+            anyhow::ensure!(
+                !l.is_empty() && l.bytes().all(|b| b.is_ascii_alphanumeric()),
+                "Invalid infra label: {:?}",
+                l
+            );
+            Ok(l)
+        })
+        .collect::<anyhow::Result<HashSet<_>>>()?;
+
+    Ok(CategoryData {
+        version: file.version,
+        exact,
+        suffix,
+        infra,
+        infra_labels,
+    })
+}
 
-        Ok(Self { exact, suffix })
+fn category_id(slug: &str) -> anyhow::Result<i32> {
+    match Category::from_slug(slug) {
+        Some(c) if c != Category::Unknown => Ok(c as i32),
+        _ => anyhow::bail!("Unknown category: {}", slug),
     }
+}
 
-    /// Lookup category for a domain
-    pub fn lookup(&self, domain: &str) -> Option<i32> {
-        // Try exact match first
-        if let Some(&cat) = self.exact.get(domain) {
-            return Some(cat);
-        }
+fn normalize_entry(domain: &str) -> anyhow::Result<String> {
+    let d = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    let valid = !d.is_empty()
+        && d.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_');
+    anyhow::ensure!(valid, "Invalid domain: {:?}", domain);
+    Ok(d)
+}
 
-        // Try suffix match
-        for (sfx, cat) in &self.suffix {
-            if domain.ends_with(sfx) {
-                return Some(*cat);
+/// Reload the category map on SIGHUP
+pub async fn reload_on_sighup(state: Arc<crate::AppState>, mut shutdown: broadcast::Receiver<()>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = shutdown.recv() => break,
+            _ = hangup.recv() => {
+                if let Err(e) = state.category_map.reload() {
+                    tracing::error!("Category map reload failed (keeping previous): {:#}", e);
+                }
             }
         }
-
-        None
     }
 }
 
@@ -218,4 +484,83 @@ mod tests {
         assert_eq!(normalize_domain("www.reddit.com"), "reddit.com");
         assert_eq!(normalize_domain("foo.bar.github.io"), "bar.github.io");
     }
+
+    #[test]
+    fn test_embedded_map_is_valid() {
+        let data = parse_map(DEFAULT_MAP).unwrap();
+        assert_eq!(data.version, SCHEMA_VERSION);
+        assert_eq!(data.exact.get("spotify.com"), Some(&CategoryWeight::sole(Category::Music as i32)));
+        assert!(data.exact.len() > 100);
+    }
+
+    #[test]
+    fn test_lookup_longest_match() {
+        let map = CategoryMap::load(None).unwrap();
+        let primary = |d: &str| primary_category(&map.lookup(d));
+        let id = |c: Category| Some(c as i32);
+        assert_eq!(primary("music.apple.com"), id(Category::Music));
+        assert_eq!(primary("podcasts.apple.com"), id(Category::Podcasts));
+        assert_eq!(primary("kindle.amazon.com"), id(Category::Reading));
+        assert_eq!(primary("www.amazon.com"), id(Category::Shopping));
+        assert_eq!(primary("news.ycombinator.com"), id(Category::Tech));
+        assert_eq!(primary("api.spotify.com"), id(Category::Music));
+        assert_eq!(primary("cs.stanford.edu"), id(Category::Reading));
+        assert!(map.lookup("apple.com").is_empty());
+        assert!(map.lookup("notspotify.com").is_empty());
+    }
+
+    #[test]
+    fn test_weighted_entries_normalized() {
+        let data = parse_map("version = 2\n[weighted]\n\"youtube.com\" = { music = 2, gaming = 1, cooking = 1 }\n").unwrap();
+        let cats = &data.exact["youtube.com"];
+        assert_eq!(cats[0], CategoryWeight { category_id: Category::Music as i32, weight: 0.5 });
+        assert_eq!(cats.len(), 3);
+        assert!((cats.iter().map(|c| c.weight).sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(primary_category(cats), Some(Category::Music as i32));
+    }
+
+    #[test]
+    fn test_parse_map_validation() {
+        let data = parse_map("version = 1\n[exact]\nmusic = [\"Bandcamp.com.\"]\n[suffix]\nreading = [\".edu\"]\n").unwrap();
+        assert_eq!(data.exact.get("bandcamp.com"), Some(&CategoryWeight::sole(Category::Music as i32)));
+        assert_eq!(data.suffix.get("edu"), Some(&CategoryWeight::sole(Category::Reading as i32)));
+
+        assert!(parse_map("version = 4\n").is_err());
+        assert!(parse_map("version = 2\n[infra]\ndomains = [\"x.net\"]\n").is_err());
+        assert!(parse_map("version = 3\n[exact]\nmusic = [\"x.com\"]\n[infra]\ndomains = [\"x.com\"]\n").is_err());
+        assert!(parse_map("version = 3\n[infra]\nlabels = [\"c.dn\"]\n").is_err());
+        assert!(parse_map("version = 1\n[weighted]\n\"x.com\" = { music = 1.0 }\n").is_err());
+        assert!(parse_map("version = 2\n[weighted]\n\"x.com\" = { music = 0.0 }\n").is_err());
+        assert!(parse_map("version = 1\n[exact]\nknitting = [\"ravelry.com\"]\n").is_err());
+        assert!(parse_map("version = 1\n[exact]\nunknown = [\"x.com\"]\n").is_err());
+        assert!(parse_map("version = 1\n[exact]\nmusic = [\"x.com\"]\ngaming = [\"x.com\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_infra_domains_and_labels() {
+        let map = CategoryMap::load(None).unwrap();
+        assert!(map.is_infra("e1234.dscb.akamaiedge.net"));
+        assert!(map.is_infra("fonts.googleapis.com"));
+        assert!(map.is_infra("1-courier.push.apple.com"));
+        assert!(map.is_infra("metrics.spotify.com"));
+        assert!(map.is_infra("cdn-eu.example.com"));
+        assert!(map.is_infra("cdn2.example.com"));
+        assert!(map.is_infra("3f9a0c2b7d1e4a56.example.net"));
+
+        // Categorized names and ordinary hosts are not infra
+        assert!(!map.is_infra("music.apple.com"));
+        assert!(!map.is_infra("open.spotify.com"));
+        assert!(!map.is_infra("www.example.com"));
+        assert!(!map.is_infra("example.com"));
+        assert!(!map.is_infra("cdnjs.com"));
+
+        // Only the leftmost label counts, and generic words aren't infra labels
+        assert!(!map.is_infra("www.cdn.example.com"));
+        assert!(!map.is_infra("stats.nba.com"));
+        assert!(!map.is_infra("live.stats.nba.com"));
+        assert!(!map.is_infra("push.example.org"));
+        assert!(!map.is_infra("img.espn.com"));
+        assert!(!map.is_infra("edge.example.com"));
+        assert!(!map.is_infra("log.example.com"));
+    }
 }
@@ -1,7 +1,11 @@
 //! Configuration from environment variables
 
+use crate::categorize::InfraPolicy;
+use crate::ingest::{IngestMode, SinkKind};
+use crate::rules::BlockMode;
 use anyhow::{Context, Result};
 use clap::Parser;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "hp-dns-gw", about = "DNS Gateway for Interest-Based Dating")]
@@ -18,14 +22,78 @@ pub struct Config {
     #[arg(long, env = "DNS_BIND_RETRIES", default_value = "0")]
     pub dns_bind_retries: u32,
 
+    /// DNS-over-TLS listen address (disabled unless cert and key are set)
+    #[arg(long, env = "DOT_LISTEN", default_value = "0.0.0.0:853")]
+    pub dot_listen: String,
+
+    /// PEM certificate chain for DNS-over-TLS (should cover *.DOT_SNI_SUFFIX)
+    #[arg(long, env = "DOT_CERT_PATH")]
+    pub dot_cert_path: Option<String>,
+
+    /// PEM private key for DNS-over-TLS
+    #[arg(long, env = "DOT_KEY_PATH")]
+    pub dot_key_path: Option<String>,
+
+    /// SNI suffix for per-user DoT hostnames (<token>.DOT_SNI_SUFFIX)
+    #[arg(long, env = "DOT_SNI_SUFFIX", default_value = "dns.heaven.computer")]
+    pub dot_sni_suffix: String,
+
     /// API server listen address
     #[arg(long, env = "API_LISTEN", default_value = "0.0.0.0:8080")]
     pub api_listen: String,
 
-    /// Upstream DNS resolver (handshake-volume-resolver)
+    /// Upstream DNS resolvers (handshake-volume-resolver), comma-separated for failover
     #[arg(long, env = "UPSTREAM_DNS", default_value = "127.0.0.1:5353")]
     pub upstream_dns: String,
 
+    /// Pooled UDP sockets per upstream resolver
+    #[arg(long, env = "UPSTREAM_SOCKETS", default_value = "4")]
+    pub upstream_sockets: usize,
+
+    /// Per-attempt upstream query timeout in milliseconds
+    #[arg(long, env = "UPSTREAM_TIMEOUT_MS", default_value = "2000")]
+    pub upstream_timeout_ms: u64,
+
+    /// Memory cap for the shared DNS response cache in bytes (0 = disabled)
+    #[arg(long, env = "DNS_CACHE_MAX_BYTES", default_value = "67108864")]
+    pub dns_cache_max_bytes: usize,
+
+    /// Subscribable blocklists as comma-separated `id=location` pairs,
+    /// where location is an http(s) URL or a local file path
+    #[arg(long, env = "BLOCKLISTS", default_value = "")]
+    pub blocklists: String,
+
+    /// Blocklist refresh interval in seconds
+    #[arg(long, env = "BLOCKLIST_REFRESH_SECS", default_value = "21600")]
+    pub blocklist_refresh_secs: u64,
+
+    /// Category map file (TOML, see data/categories.toml); the embedded copy
+    /// is used when unset. Reloaded on SIGHUP.
+    #[arg(long, env = "CATEGORY_MAP_PATH")]
+    pub category_map_path: Option<String>,
+
+    /// Default response for blocked names: null_ip, nxdomain, refused or sinkhole
+    /// (users and individual rules can override)
+    #[arg(long, env = "BLOCK_MODE", value_enum, default_value = "null_ip")]
+    pub block_mode: BlockMode,
+
+    /// TTL in seconds for synthesized block responses
+    #[arg(long, env = "BLOCK_TTL", default_value = "60")]
+    pub block_ttl: u32,
+
+    /// IPv4 address blocked A queries resolve to in sinkhole mode (block page host)
+    #[arg(long, env = "BLOCK_SINKHOLE_IPV4")]
+    pub block_sinkhole_ipv4: Option<Ipv4Addr>,
+
+    /// IPv6 address blocked AAAA queries resolve to in sinkhole mode
+    #[arg(long, env = "BLOCK_SINKHOLE_IPV6")]
+    pub block_sinkhole_ipv6: Option<Ipv6Addr>,
+
+    /// Listen address for the "blocked by Heaven" page (disabled when unset);
+    /// should be reachable at BLOCK_SINKHOLE_IPV4, e.g. 10.13.13.1:80
+    #[arg(long, env = "BLOCK_PAGE_LISTEN")]
+    pub block_page_listen: Option<String>,
+
     /// VPN subnet for device IP allocation
     #[arg(long, env = "VPN_SUBNET", default_value = "10.13.13.0/24")]
     pub vpn_subnet: String,
@@ -42,32 +110,137 @@ pub struct Config {
     #[arg(long, env = "DATABASE_URL")]
     pub database_url: String,
 
-    /// Tinybird API token
+    /// Where events go: tinybird, postgres (this database), file (rotating
+    /// NDJSON, for development) or clickhouse
+    #[arg(long, env = "EVENT_SINK", value_enum, default_value = "tinybird")]
+    pub event_sink: SinkKind,
+
+    /// Tinybird API token (required for EVENT_SINK=tinybird)
     #[arg(long, env = "TINYBIRD_TOKEN")]
-    pub tinybird_token: String,
+    pub tinybird_token: Option<String>,
 
     /// Tinybird API endpoint
     #[arg(long, env = "TINYBIRD_ENDPOINT", default_value = "https://api.tinybird.co")]
     pub tinybird_endpoint: String,
 
+    /// Directory for the file sink
+    #[arg(long, env = "EVENT_FILE_DIR", default_value = "./events")]
+    pub event_file_dir: String,
+
+    /// File sink: rotate a stream's file once it would exceed this size
+    #[arg(long, env = "EVENT_FILE_MAX_BYTES", default_value = "104857600")]
+    pub event_file_max_bytes: u64,
+
+    /// File sink: rotated files kept per stream
+    #[arg(long, env = "EVENT_FILE_KEEP", default_value = "10")]
+    pub event_file_keep: usize,
+
+    /// ClickHouse HTTP interface URL (required for EVENT_SINK=clickhouse)
+    #[arg(long, env = "CLICKHOUSE_URL")]
+    pub clickhouse_url: Option<String>,
+
+    /// ClickHouse database holding dns_events and interest_histograms
+    #[arg(long, env = "CLICKHOUSE_DATABASE", default_value = "default")]
+    pub clickhouse_database: String,
+
+    /// ClickHouse user
+    #[arg(long, env = "CLICKHOUSE_USER")]
+    pub clickhouse_user: Option<String>,
+
+    /// ClickHouse password
+    #[arg(long, env = "CLICKHOUSE_PASSWORD")]
+    pub clickhouse_password: Option<String>,
+
     /// HMAC secret for domain hashing
     #[arg(long, env = "HMAC_SECRET")]
     pub hmac_secret: String,
 
+    /// Additional HMAC keys as comma-separated `id=secret` or
+    /// `id@<RFC 3339 time>=secret` (scheduled); the latest active key is used
+    #[arg(long, env = "HMAC_KEYS")]
+    pub hmac_keys: Option<String>,
+
+    /// Derive a fresh HMAC key every this many seconds so HMACs can't be
+    /// correlated across epochs (unset = one key until rotation)
+    #[arg(long, env = "HMAC_EPOCH_SECS")]
+    pub hmac_epoch_secs: Option<u64>,
+
     /// JWT secret for auth tokens
     #[arg(long, env = "JWT_SECRET")]
     pub jwt_secret: String,
 
+    /// Bearer token for the /admin API (unset = token access disabled)
+    #[arg(long, env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Comma-separated wallet addresses whose JWTs may use the /admin API
+    #[arg(long, env = "ADMIN_WALLETS")]
+    pub admin_wallets: Option<String>,
+
     /// Auth domain for SIWE messages
     #[arg(long, env = "AUTH_DOMAIN", default_value = "hp-dns-gw.local")]
     pub auth_domain: String,
 
-    /// Tinybird batch size (events per request)
-    #[arg(long, env = "TINYBIRD_BATCH_SIZE", default_value = "1000")]
+    /// Events per sink request (all sinks)
+    #[arg(long, env = "EVENT_BATCH_SIZE")]
+    pub event_batch_size: Option<usize>,
+
+    /// Former name of EVENT_BATCH_SIZE, still honoured
+    #[arg(long, env = "TINYBIRD_BATCH_SIZE", default_value = "1000", hide = true)]
     pub tinybird_batch_size: usize,
 
-    /// Tinybird flush interval in seconds
-    #[arg(long, env = "TINYBIRD_FLUSH_INTERVAL", default_value = "5")]
+    /// What to send to the event sink: `events` (one row per query) or `aggregate`
+    /// (periodic per-wallet category histograms only)
+    #[arg(long, env = "INGEST_MODE", value_enum, default_value = "events")]
+    pub ingest_mode: IngestMode,
+
+    /// Aggregation window in seconds (aggregate mode)
+    #[arg(long, env = "AGGREGATE_INTERVAL_SECS", default_value = "3600")]
+    pub aggregate_interval_secs: u64,
+
+    /// Differential privacy budget per query for aggregate histograms
+    /// (Laplace noise of scale 1/epsilon; unset = exact counts)
+    #[arg(long, env = "AGGREGATE_DP_EPSILON")]
+    pub aggregate_dp_epsilon: Option<f64>,
+
+    /// Privacy policy for sensitive categories as comma-separated `slug=action`
+    /// pairs, where action is keep, redact (strip wallet/device/domain) or drop
+    #[arg(
+        long,
+        env = "SENSITIVE_CATEGORY_POLICY",
+        default_value = "health=redact,adult=redact,religion=redact,politics=redact"
+    )]
+    pub sensitive_category_policy: String,
+
+    /// Events for CDN/analytics/telemetry hosts: `tag` (logged uncategorized
+    /// with infra set) or `drop`
+    #[arg(long, env = "INFRA_POLICY", value_enum, default_value = "tag")]
+    pub infra_policy: InfraPolicy,
+
+    /// Collapse repeated lookups of the same (device, eTLD+1, qtype) within
+    /// this many seconds into one event with a count (0 = off)
+    #[arg(long, env = "DEDUP_WINDOW_SECS", default_value = "10")]
+    pub dedup_window_secs: u64,
+
+    /// Idle gap after which a new lookup of a site starts a new visit
+    #[arg(long, env = "SESSION_GAP_SECS", default_value = "1800")]
+    pub session_gap_secs: u64,
+
+    /// Write-ahead spool directory for event batches (unset = in-memory
+    /// requeue only; undelivered events are lost on restart)
+    #[arg(long, env = "EVENT_SPOOL_DIR")]
+    pub event_spool_dir: Option<String>,
+
+    /// Spool size cap; the oldest batches are evicted beyond it
+    #[arg(long, env = "EVENT_SPOOL_MAX_BYTES", default_value = "1073741824")]
+    pub event_spool_max_bytes: u64,
+
+    /// Flush interval in seconds (all sinks)
+    #[arg(long, env = "EVENT_FLUSH_INTERVAL")]
+    pub event_flush_interval: Option<u64>,
+
+    /// Former name of EVENT_FLUSH_INTERVAL, still honoured
+    #[arg(long, env = "TINYBIRD_FLUSH_INTERVAL", default_value = "5", hide = true)]
     pub tinybird_flush_interval: u64,
 
     /// Heaven Names API base URL (e.g., https://api.heaven.xyz)
@@ -92,4 +265,14 @@ impl Config {
 
         Config::try_parse().context("Failed to parse configuration")
     }
+
+    /// Events per sink request (EVENT_BATCH_SIZE, else TINYBIRD_BATCH_SIZE)
+    pub fn event_batch_size(&self) -> usize {
+        self.event_batch_size.unwrap_or(self.tinybird_batch_size)
+    }
+
+    /// Seconds between flushes (EVENT_FLUSH_INTERVAL, else TINYBIRD_FLUSH_INTERVAL)
+    pub fn event_flush_interval(&self) -> u64 {
+        self.event_flush_interval.unwrap_or(self.tinybird_flush_interval)
+    }
 }
@@ -0,0 +1,413 @@
+//! Shared DNS response cache in front of the upstream resolver
+//!
+//! Positive answers are cached for the smallest record TTL; NXDOMAIN and
+//! NODATA answers are cached per RFC 2308 (min of the SOA TTL and SOA
+//! MINIMUM), and only when the authority section carries an SOA. Entries are
+//! keyed on qname/qtype/class (plus the DO bit, since DNSSEC-aware clients
+//! get different answers, and the requester's UDP payload size class, so an
+//! answer fetched over TCP or for a large EDNS buffer is never replayed to a
+//! client limited to 512 bytes). On a hit the ID, question and TTLs are rewritten
+//! for the requesting client.
+//!
+//! Expired entries are dropped when looked up and by a periodic sweep. When
+//! the memory cap is reached, inserts evict in insertion order (FIFO) so the
+//! query path never scans the whole map.
+
+use crate::AppState;
+use dashmap::DashMap;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{RData, Record};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Upper bound on positive TTLs (some zones publish week-long TTLs)
+const MAX_POSITIVE_TTL: u32 = 86_400;
+/// Upper bound on negative TTLs (RFC 2308 suggests 1-3 hours)
+const MAX_NEGATIVE_TTL: u32 = 3_600;
+/// Rough per-entry bookkeeping overhead on top of the wire size
+const ENTRY_OVERHEAD: usize = 128;
+/// UDP payload size classes: plain DNS, the DNS Flag Day 2020 EDNS default, large EDNS
+const SIZE_CLASSES: [u16; 3] = [512, 1232, 4096];
+/// How often expired entries are swept out
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    qname: String,
+    qtype: u16,
+    qclass: u16,
+    dnssec_ok: bool,
+    /// Largest response the requester accepts over UDP (a `SIZE_CLASSES` entry)
+    size_class: u16,
+}
+
+struct CacheEntry {
+    response: Message,
+    inserted_at: Instant,
+    ttl: u32,
+    size: usize,
+}
+
+/// TTL-respecting response cache with a memory cap
+pub struct ResponseCache {
+    entries: DashMap<CacheKey, CacheEntry>,
+    /// Keys in insertion order for eviction, tagged with `inserted_at` so
+    /// stale positions (replaced or already removed entries) are skipped
+    order: Mutex<VecDeque<(CacheKey, Instant)>>,
+    max_bytes: usize,
+    bytes: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// Create a cache holding at most `max_bytes` of responses (0 disables caching)
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: DashMap::new(),
+            order: Mutex::new(VecDeque::new()),
+            max_bytes,
+            bytes: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up a cached answer for `request`, rewritten for this client
+    pub fn get(&self, request: &Message) -> Option<Vec<u8>> {
+        if self.max_bytes == 0 {
+            return None;
+        }
+        let key = cache_key(request)?;
+
+        let resp = {
+            let Some(entry) = self.entries.get(&key) else {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            };
+
+            let age = entry.inserted_at.elapsed().as_secs() as u32;
+            if age >= entry.ttl {
+                drop(entry);
+                self.remove(&key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+
+            let mut resp = entry.response.clone();
+            resp.set_id(request.id());
+            resp.set_recursion_desired(request.recursion_desired());
+            // Echo the client's question (preserves 0x20 case randomization)
+            *resp.queries_mut() = request.queries().to_vec();
+            age_records(resp.answers_mut(), age);
+            age_records(resp.name_servers_mut(), age);
+            age_records(resp.additionals_mut(), age);
+            resp
+        };
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        resp.to_bytes().ok()
+    }
+
+    /// Cache an upstream response for `request` if it is cacheable
+    pub fn insert(&self, request: &Message, response: &[u8]) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        let Some(key) = cache_key(request) else {
+            return;
+        };
+        let Ok(parsed) = Message::from_bytes(response) else {
+            return;
+        };
+        let Some(ttl) = cacheable_ttl(&parsed) else {
+            return;
+        };
+
+        // Answers the requester's class can't take over UDP (e.g. fetched over
+        // TCP after TC) are only ever served fresh
+        if response.len() > key.size_class as usize {
+            return;
+        }
+
+        let size = response.len() + key.qname.len() + ENTRY_OVERHEAD;
+        if size > self.max_bytes {
+            return;
+        }
+
+        self.make_room(size);
+
+        let inserted_at = Instant::now();
+        let entry = CacheEntry {
+            response: parsed,
+            inserted_at,
+            ttl,
+            size,
+        };
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+        if let Some(old) = self.entries.insert(key.clone(), entry) {
+            self.bytes.fetch_sub(old.size, Ordering::Relaxed);
+        }
+        self.order.lock().unwrap().push_back((key, inserted_at));
+    }
+
+    /// Drop expired entries and forget their eviction positions
+    pub fn sweep(&self) {
+        self.entries.retain(|_, e| {
+            let live = e.inserted_at.elapsed().as_secs() < e.ttl as u64;
+            if !live {
+                self.bytes.fetch_sub(e.size, Ordering::Relaxed);
+            }
+            live
+        });
+
+        self.order.lock().unwrap().retain(|(key, inserted_at)| {
+            self.entries
+                .get(key)
+                .is_some_and(|e| e.inserted_at == *inserted_at)
+        });
+    }
+
+    /// Number of cached responses
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Approximate memory held by cached responses
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn remove(&self, key: &CacheKey) {
+        if let Some((_, old)) = self.entries.remove(key) {
+            self.bytes.fetch_sub(old.size, Ordering::Relaxed);
+        }
+    }
+
+    /// Evict the oldest insertions until `incoming` more bytes fit
+    fn make_room(&self, incoming: usize) {
+        if self.bytes() + incoming <= self.max_bytes {
+            return;
+        }
+
+        let mut order = self.order.lock().unwrap();
+        while self.bytes() + incoming > self.max_bytes {
+            let Some((key, inserted_at)) = order.pop_front() else {
+                break;
+            };
+            if let Some((_, old)) = self
+                .entries
+                .remove_if(&key, |_, e| e.inserted_at == inserted_at)
+            {
+                self.bytes.fetch_sub(old.size, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Periodically sweep expired responses out of the shared cache
+pub async fn sweep_loop(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) {
+    let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+    ticker.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.recv() => break,
+            _ = ticker.tick() => state.response_cache.sweep(),
+        }
+    }
+}
+
+fn age_records(records: &mut [Record], age: u32) {
+    for record in records {
+        let ttl = record.ttl();
+        record.set_ttl(ttl.saturating_sub(age));
+    }
+}
+
+fn cache_key(request: &Message) -> Option<CacheKey> {
+    let query = request.queries().first()?;
+    let edns = request.extensions().as_ref();
+    let advertised = edns.map_or(512, |e| e.max_payload());
+    Some(CacheKey {
+        qname: query.name().to_ascii().to_ascii_lowercase(),
+        qtype: query.query_type().into(),
+        qclass: query.query_class().into(),
+        dnssec_ok: edns.is_some_and(|e| e.dnssec_ok()),
+        size_class: SIZE_CLASSES
+            .into_iter()
+            .rfind(|&c| c <= advertised)
+            .unwrap_or(SIZE_CLASSES[0]),
+    })
+}
+
+/// TTL to cache a response for, or None if it must not be cached
+fn cacheable_ttl(resp: &Message) -> Option<u32> {
+    if resp.truncated() {
+        return None;
+    }
+
+    let negative = match resp.response_code() {
+        ResponseCode::NXDomain => true,
+        ResponseCode::NoError => resp.answers().is_empty(),
+        _ => return None,
+    };
+
+    if !negative {
+        let ttl = resp.answers().iter().map(|r| r.ttl()).min()?;
+        return (ttl > 0).then_some(ttl.min(MAX_POSITIVE_TTL));
+    }
+
+    // RFC 2308 section 5: negative TTL is min(SOA TTL, SOA MINIMUM); no SOA, no caching
+    let ttl = resp.name_servers().iter().find_map(|r| match r.data() {
+        Some(RData::SOA(soa)) => Some(r.ttl().min(soa.minimum())),
+        _ => None,
+    })?;
+    (ttl > 0).then_some(ttl.min(MAX_NEGATIVE_TTL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, MessageType, Query};
+    use hickory_proto::rr::rdata::{A, SOA};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::net::Ipv4Addr;
+
+    fn request(id: u16, qname: &str) -> Message {
+        let mut msg = Message::new();
+        msg.set_id(id);
+        msg.add_query(Query::query(Name::from_ascii(qname).unwrap(), RecordType::A));
+        msg
+    }
+
+    fn response(req: &Message, code: ResponseCode) -> Message {
+        let mut resp = Message::new();
+        resp.set_id(req.id());
+        resp.set_message_type(MessageType::Response);
+        resp.set_response_code(code);
+        resp.add_query(req.queries()[0].clone());
+        resp
+    }
+
+    #[test]
+    fn test_positive_hit_rewrites_id() {
+        let cache = ResponseCache::new(1 << 20);
+        let req = request(1, "spotify.com.");
+        let mut resp = response(&req, ResponseCode::NoError);
+        resp.add_answer(Record::from_rdata(
+            req.queries()[0].name().clone(),
+            300,
+            RData::A(A(Ipv4Addr::new(1, 2, 3, 4))),
+        ));
+        cache.insert(&req, &resp.to_bytes().unwrap());
+
+        let hit = cache.get(&request(42, "Spotify.com.")).unwrap();
+        let hit = Message::from_bytes(&hit).unwrap();
+        assert_eq!(hit.id(), 42);
+        assert_eq!(hit.answers().len(), 1);
+        assert!(hit.answers()[0].ttl() <= 300);
+        assert_eq!((cache.hits(), cache.misses()), (1, 0));
+    }
+
+    #[test]
+    fn test_negative_caching_requires_soa() {
+        let cache = ResponseCache::new(1 << 20);
+        let req = request(1, "nope.example.");
+
+        let bare = response(&req, ResponseCode::NXDomain);
+        cache.insert(&req, &bare.to_bytes().unwrap());
+        assert!(cache.get(&req).is_none());
+
+        let mut with_soa = response(&req, ResponseCode::NXDomain);
+        let zone = Name::from_ascii("example.").unwrap();
+        let soa = SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, 30);
+        with_soa.add_name_server(Record::from_rdata(zone, 900, RData::SOA(soa)));
+        cache.insert(&req, &with_soa.to_bytes().unwrap());
+
+        let hit = Message::from_bytes(&cache.get(&req).unwrap()).unwrap();
+        assert_eq!(hit.response_code(), ResponseCode::NXDomain);
+    }
+
+    #[test]
+    fn test_full_cache_evicts_oldest() {
+        let answer = |req: &Message| {
+            let mut resp = response(req, ResponseCode::NoError);
+            resp.add_answer(Record::from_rdata(
+                req.queries()[0].name().clone(),
+                300,
+                RData::A(A(Ipv4Addr::new(1, 2, 3, 4))),
+            ));
+            resp.to_bytes().unwrap()
+        };
+        let first = request(1, "a.example.");
+        let size = answer(&first).len() + "a.example.".len() + ENTRY_OVERHEAD;
+        let cache = ResponseCache::new(size * 2);
+
+        cache.insert(&first, &answer(&first));
+        // Re-inserting leaves a stale queue position behind
+        cache.insert(&first, &answer(&first));
+        let second = request(2, "b.example.");
+        cache.insert(&second, &answer(&second));
+        let third = request(3, "c.example.");
+        cache.insert(&third, &answer(&third));
+
+        assert!(cache.get(&first).is_none());
+        assert!(cache.get(&second).is_some());
+        assert!(cache.get(&third).is_some());
+        assert_eq!(cache.bytes(), size * 2);
+
+        cache.sweep();
+        assert_eq!(cache.order.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_large_answers_keyed_by_payload_size() {
+        let mut big = request(1, "big.example.");
+        let mut edns = Edns::new();
+        edns.set_max_payload(4096);
+        big.set_edns(edns);
+
+        let mut resp = response(&big, ResponseCode::NoError);
+        for i in 0..40 {
+            resp.add_answer(Record::from_rdata(
+                big.queries()[0].name().clone(),
+                300,
+                RData::A(A(Ipv4Addr::new(10, 0, 0, i))),
+            ));
+        }
+        let wire = resp.to_bytes().unwrap();
+        assert!(wire.len() > 512);
+
+        // Cached for large-EDNS clients only
+        let cache = ResponseCache::new(1 << 20);
+        cache.insert(&big, &wire);
+        assert!(cache.get(&big).is_some());
+        assert!(cache.get(&request(2, "big.example.")).is_none());
+
+        // An oversized answer for a plain client (TCP after TC) is not cached
+        let plain = request(3, "big.example.");
+        cache.insert(&plain, &wire);
+        assert!(cache.get(&plain).is_none());
+    }
+
+    #[test]
+    fn test_servfail_not_cached() {
+        let cache = ResponseCache::new(1 << 20);
+        let req = request(1, "flaky.example.");
+        cache.insert(&req, &response(&req, ResponseCode::ServFail).to_bytes().unwrap());
+        assert_eq!(cache.len(), 0);
+    }
+}
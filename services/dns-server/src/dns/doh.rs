@@ -0,0 +1,118 @@
+//! DNS-over-HTTPS endpoint (RFC 8484)
+//!
+//! Serves `/dns-query` on the API router for clients that can't route UDP/53
+//! through the tunnel (Android, browsers). Queries go through the same
+//! `handle_query_as` path as UDP/TCP, so blocking, .heaven resolution and
+//! Tinybird logging behave identically. Identity comes from the JWT rather
+//! than the VPN IP, since DoH clients are usually not on the tunnel.
+
+use super::handler::handle_query_as;
+use crate::api::AuthUser;
+use crate::users::CachedUser;
+use crate::AppState;
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hickory_proto::op::Message;
+use hickory_proto::serialize::binary::BinDecodable;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Media type for wire-format DNS messages
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Largest DNS message we accept (same bound as the TCP length prefix)
+const MAX_MESSAGE_SIZE: usize = 65535;
+
+#[derive(Deserialize)]
+pub struct DohParams {
+    dns: String,
+}
+
+/// GET /dns-query?dns=<base64url>
+pub async fn doh_get(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<DohParams>,
+) -> Result<Response, (StatusCode, String)> {
+    // RFC 8484 mandates unpadded base64url, but tolerate padding from lenient clients
+    let pkt = URL_SAFE_NO_PAD
+        .decode(params.dns.trim_end_matches('='))
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid dns parameter".to_string()))?;
+
+    resolve(&state, claims, addr, pkt).await
+}
+
+/// POST /dns-query with an application/dns-message body
+pub async fn doh_post(
+    State(state): State<Arc<AppState>>,
+    AuthUser(claims): AuthUser,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if content_type != DNS_MESSAGE {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {}", DNS_MESSAGE),
+        ));
+    }
+
+    resolve(&state, claims, addr, body.to_vec()).await
+}
+
+async fn resolve(
+    state: &Arc<AppState>,
+    claims: crate::auth::Claims,
+    addr: SocketAddr,
+    pkt: Vec<u8>,
+) -> Result<Response, (StatusCode, String)> {
+    if pkt.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Empty DNS message".to_string()));
+    }
+    if pkt.len() > MAX_MESSAGE_SIZE {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "DNS message too large".to_string()));
+    }
+
+    // JWTs identify the user, not a device
+    let user = CachedUser {
+        user_id: claims.user_id,
+        wallet_address: claims.sub,
+        device_id: Uuid::nil(),
+        vpn_ip: addr.ip(),
+    };
+
+    let resp = handle_query_as(state, pkt, addr.ip(), Some(user))
+        .await
+        .ok_or((StatusCode::BAD_REQUEST, "Malformed DNS message".to_string()))?;
+
+    // RFC 8484 5.1: freshness lifetime should not exceed the smallest answer TTL
+    let max_age = min_answer_ttl(&resp);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, DNS_MESSAGE.to_string()),
+            (header::CACHE_CONTROL, format!("max-age={}", max_age)),
+        ],
+        resp,
+    )
+        .into_response())
+}
+
+fn min_answer_ttl(resp: &[u8]) -> u32 {
+    Message::from_bytes(resp)
+        .ok()
+        .and_then(|m| m.answers().iter().map(|r| r.ttl()).min())
+        .unwrap_or(0)
+}
@@ -1,7 +1,10 @@
 //! DNS query handler - categorize, log, forward
 
-use crate::categorize::normalize_domain;
+use crate::categorize::{normalize_domain, primary_category, InfraPolicy};
+use crate::config::Config;
 use crate::ingest::DnsEvent;
+use crate::rules::{BlockMode, Verdict};
+use crate::users::CachedUser;
 use crate::AppState;
 use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
 use hickory_proto::rr::rdata::{A, AAAA};
@@ -12,6 +15,18 @@ use std::sync::Arc;
 use std::time::Instant;
 
 pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -> Option<Vec<u8>> {
+    // Lookup user by VPN IP
+    let user = state.user_cache.get_by_ip(&src_ip).await;
+    handle_query_as(state, pkt, src_ip, user).await
+}
+
+/// Handle a query for an already-identified user (DoH/DoT clients are not on a VPN IP)
+pub async fn handle_query_as(
+    state: &Arc<AppState>,
+    pkt: Vec<u8>,
+    src_ip: IpAddr,
+    user: Option<CachedUser>,
+) -> Option<Vec<u8>> {
     let start = Instant::now();
 
     // Parse DNS message
@@ -26,12 +41,12 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
     // Extract registrable domain (eTLD+1)
     let etld1 = normalize_domain(&qname_norm);
 
-    // Lookup user by VPN IP
-    let user = state.user_cache.get_by_ip(&src_ip).await;
     let (wallet_id, device_id) = match &user {
         Some(u) => {
-            // Update last seen for this device
-            state.last_seen.touch(u.device_id);
+            // Update last seen for this device (DoH identities carry no device)
+            if !u.device_id.is_nil() {
+                state.last_seen.touch(u.device_id);
+            }
             (u.wallet_address.clone(), u.device_id)
         }
         None => {
@@ -40,23 +55,50 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
         }
     };
 
-    // Categorize domain
-    let category_id = state.category_map.lookup(&etld1);
+    // What the user/device lets us log (checked before every queue_event)
+    let logging_mode = user
+        .as_ref()
+        .map(|u| state.logging_prefs.effective(u.user_id, u.device_id))
+        .unwrap_or_default();
+
+    // CDN/analytics/telemetry lookups aren't interest signal: they are never
+    // categorized, and are logged with `infra` set or not at all
+    let infra = state.category_map.is_infra(&qname_norm);
+
+    // Categorize domain (full qname, so subdomain entries like music.apple.com match).
+    // Category block rules match the dominant category only.
+    let categories = if infra { Vec::new() } else { state.category_map.lookup(&qname_norm) };
+    let category_id = primary_category(&categories);
 
-    // Compute HMAC for privacy-preserving storage
-    let domain_hmac = state.tinybird.hmac_domain(&etld1, &state.config.hmac_secret);
+    // Compute HMAC for privacy-preserving storage (key_id says which key)
+    let (key_id, domain_hmac) = state.hmac_keys.hmac_domain(&etld1, chrono::Utc::now());
 
-    // Check if domain is blocked for this user
-    let is_blocked = if let Some(ref u) = user {
-        state.rules_cache.is_blocked(&u.user_id, &etld1).await
+    // Check if domain is blocked for this user (full qname, so exact/glob rules can match).
+    // The user's own rules (including allow overrides) win over subscribed blocklists.
+    // Yields the block response mode: the rule's, else the user's, else BLOCK_MODE.
+    let block_mode = if let Some(ref u) = user {
+        let mode = match state
+            .rules_cache
+            .verdict(&u.user_id, &u.device_id, &qname_norm, category_id)
+            .await
+        {
+            Some(Verdict::Allow) => None,
+            Some(Verdict::Block(mode)) => Some(mode),
+            None if state.blocklists.is_blocked(&u.user_id, &qname_norm).await => {
+                Some(state.rules_cache.block_mode(&u.user_id).await)
+            }
+            None => None,
+        };
+        mode.map(|m| m.unwrap_or(state.config.block_mode))
     } else {
-        false
+        None
     };
 
     // Check for .heaven TLD (before block check - always allow .heaven queries)
     if let Some(ref heaven) = state.heaven {
         if let Some(resp) = heaven.maybe_handle(&msg, &qname_norm, qtype).await {
             let latency_ms = start.elapsed().as_millis() as u32;
+            state.metrics.record_query("heaven", qtype);
 
             // Queue event for Tinybird (mark as "heaven" action)
             // Use etld1 (registrable domain) for consistency with other events
@@ -65,13 +107,20 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
                 wallet_id: wallet_id.clone(),
                 device_id,
                 etld1: etld1.clone(),
-                domain_hmac: state.tinybird.hmac_domain(&etld1, &state.config.hmac_secret),
+                domain_hmac: domain_hmac.clone(),
+                key_id: key_id.clone(),
                 qtype: format!("{:?}", qtype),
                 action: "heaven".to_string(),
-                category_id: None,
+                categories: Vec::new(),
                 latency_ms,
+                count: 1,
+                session_id: None,
+                session_start: false,
+                infra: false,
             };
-            state.tinybird.queue_event(event).await;
+            if let Some(event) = logging_mode.apply(event) {
+                state.events.queue_event(event);
+            }
 
             tracing::debug!(
                 "{} -> {} ({:?}) [heaven] {}ms",
@@ -86,19 +135,25 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
     }
 
     // Either block or forward to upstream
-    let (response, action) = if is_blocked {
-        // Return blocked response (0.0.0.0 / ::)
-        let resp = build_blocked_response(&msg, &qname_norm, qtype)?;
+    let (response, action) = if let Some(mode) = block_mode {
+        let resp = build_blocked_response(&msg, &qname_norm, qtype, mode, &state.config)?;
         tracing::info!(
             src = %src_ip,
             domain = %etld1,
+            mode = mode.as_str(),
             "Blocked by user rule"
         );
         (resp, "block")
+    } else if let Some(resp) = state.response_cache.get(&msg) {
+        // Serve from the shared response cache
+        (resp, "allow")
     } else {
         // Forward to upstream resolver
-        match super::upstream::forward(&state.config.upstream_dns, &pkt).await {
-            Ok(resp) => (resp, "allow"),
+        match state.upstream.forward(&pkt).await {
+            Ok(resp) => {
+                state.response_cache.insert(&msg, &resp);
+                (resp, "allow")
+            }
             Err(e) => {
                 tracing::warn!("Upstream error for {}: {}", qname_norm, e);
                 // Return SERVFAIL
@@ -109,6 +164,7 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
     };
 
     let latency_ms = start.elapsed().as_millis() as u32;
+    state.metrics.record_query(action, qtype);
 
     // Queue event for Tinybird
     let event = DnsEvent {
@@ -117,12 +173,20 @@ pub async fn handle_query(state: &Arc<AppState>, pkt: Vec<u8>, src_ip: IpAddr) -
         device_id,
         etld1: etld1.clone(),
         domain_hmac,
+        key_id,
         qtype: format!("{:?}", qtype),
         action: action.to_string(),
-        category_id,
+        categories,
         latency_ms,
+        count: 1,
+        session_id: None,
+        session_start: false,
+        infra,
     };
-    state.tinybird.queue_event(event).await;
+    let keep = !infra || state.config.infra_policy == InfraPolicy::Tag;
+    if let Some(event) = logging_mode.apply(event).filter(|_| keep) {
+        state.events.queue_event(event);
+    }
 
     tracing::debug!(
         "{} -> {} ({:?}) [{}] cat={:?} {}ms",
@@ -161,8 +225,20 @@ fn build_servfail(query: &Message) -> Option<Vec<u8>> {
     resp.to_bytes().ok()
 }
 
-/// Build blocked response (0.0.0.0 for A, :: for AAAA)
-fn build_blocked_response(query: &Message, qname: &str, qtype: RecordType) -> Option<Vec<u8>> {
+/// Build the response for a blocked name according to `mode`.
+///
+/// In the IP modes, HTTPS/SVCB queries get an empty NOERROR answer: the
+/// upstream record's ipv4hint/ipv6hint (or ECH config) would otherwise let
+/// browsers connect without ever using the A/AAAA answers we rewrite. Other
+/// record types get NXDOMAIN. Sinkhole AAAA queries without an IPv6 sinkhole
+/// get NODATA, so clients fall back to the IPv4 block page.
+fn build_blocked_response(
+    query: &Message,
+    qname: &str,
+    qtype: RecordType,
+    mode: BlockMode,
+    config: &Config,
+) -> Option<Vec<u8>> {
     let mut resp = Message::new();
     resp.set_id(query.id());
     resp.set_message_type(MessageType::Response);
@@ -173,29 +249,84 @@ fn build_blocked_response(query: &Message, qname: &str, qtype: RecordType) -> Op
     let name = Name::from_ascii(format!("{}.", qname)).ok()?;
     resp.add_query(Query::query(name.clone(), qtype));
 
-    const TTL: u32 = 60;
-
-    match qtype {
-        RecordType::A => {
-            resp.add_answer(Record::from_rdata(
-                name,
-                TTL,
-                RData::A(A(Ipv4Addr::UNSPECIFIED)),
-            ));
-            resp.set_response_code(ResponseCode::NoError);
+    // Sinkhole falls back to null IPs until a sinkhole address is configured
+    let (v4, v6) = match mode {
+        BlockMode::Sinkhole if config.block_sinkhole_ipv4.is_some() => {
+            (config.block_sinkhole_ipv4, config.block_sinkhole_ipv6)
         }
-        RecordType::AAAA => {
-            resp.add_answer(Record::from_rdata(
-                name,
-                TTL,
-                RData::AAAA(AAAA(Ipv6Addr::UNSPECIFIED)),
-            ));
-            resp.set_response_code(ResponseCode::NoError);
+        _ => (Some(Ipv4Addr::UNSPECIFIED), Some(Ipv6Addr::UNSPECIFIED)),
+    };
+    let ttl = config.block_ttl;
+
+    let code = match (mode, qtype) {
+        (BlockMode::Refused, _) => ResponseCode::Refused,
+        (BlockMode::Nxdomain, _) => ResponseCode::NXDomain,
+        (_, RecordType::A) => {
+            if let Some(ip) = v4 {
+                resp.add_answer(Record::from_rdata(name, ttl, RData::A(A(ip))));
+            }
+            ResponseCode::NoError
         }
-        _ => {
-            resp.set_response_code(ResponseCode::NXDomain);
+        (_, RecordType::AAAA) => {
+            if let Some(ip) = v6 {
+                resp.add_answer(Record::from_rdata(name, ttl, RData::AAAA(AAAA(ip))));
+            }
+            ResponseCode::NoError
         }
-    }
+        (_, RecordType::HTTPS | RecordType::SVCB) => ResponseCode::NoError,
+        _ => ResponseCode::NXDomain,
+    };
+    resp.set_response_code(code);
 
     resp.to_bytes().ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn blocked(qtype: RecordType, mode: BlockMode, env: &[&str]) -> Message {
+        let mut args = vec!["hp-dns-gw", "--database-url=x", "--tinybird-token=x", "--hmac-secret=x", "--jwt-secret=x"];
+        args.extend_from_slice(env);
+        let config = Config::try_parse_from(args).unwrap();
+
+        let mut query = Message::new();
+        query.set_id(7);
+        query.add_query(Query::query(Name::from_ascii("ads.example.com.").unwrap(), qtype));
+        let resp = build_blocked_response(&query, "ads.example.com", qtype, mode, &config).unwrap();
+        Message::from_bytes(&resp).unwrap()
+    }
+
+    #[test]
+    fn test_block_modes() {
+        let resp = blocked(RecordType::A, BlockMode::NullIp, &[]);
+        assert_eq!(resp.answers()[0].data(), Some(&RData::A(A(Ipv4Addr::UNSPECIFIED))));
+        assert_eq!(resp.answers()[0].ttl(), 60);
+
+        let resp = blocked(RecordType::A, BlockMode::Refused, &[]);
+        assert_eq!(resp.response_code(), ResponseCode::Refused);
+        assert!(resp.answers().is_empty());
+
+        let resp = blocked(RecordType::HTTPS, BlockMode::Nxdomain, &[]);
+        assert_eq!(resp.response_code(), ResponseCode::NXDomain);
+
+        let sinkhole = ["--block-sinkhole-ipv4=10.13.13.1", "--block-ttl=5"];
+        let resp = blocked(RecordType::A, BlockMode::Sinkhole, &sinkhole);
+        assert_eq!(resp.answers()[0].data(), Some(&RData::A(A(Ipv4Addr::new(10, 13, 13, 1)))));
+        assert_eq!(resp.answers()[0].ttl(), 5);
+        // No IPv6 sinkhole: NODATA so clients use the IPv4 block page
+        let resp = blocked(RecordType::AAAA, BlockMode::Sinkhole, &sinkhole);
+        assert_eq!(resp.response_code(), ResponseCode::NoError);
+        assert!(resp.answers().is_empty());
+    }
+
+    #[test]
+    fn test_https_records_never_leak_hints() {
+        for qtype in [RecordType::HTTPS, RecordType::SVCB] {
+            let resp = blocked(qtype, BlockMode::NullIp, &[]);
+            assert_eq!(resp.response_code(), ResponseCode::NoError);
+            assert!(resp.answers().is_empty());
+        }
+    }
+}
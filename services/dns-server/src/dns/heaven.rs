@@ -22,6 +22,7 @@ use hickory_proto::{
     },
     serialize::binary::BinEncodable,
 };
+use crate::metrics::Histogram;
 use reqwest::Client;
 use serde::Deserialize;
 use tokio::sync::Mutex;
@@ -38,6 +39,8 @@ pub struct HeavenResolver {
     cache: Arc<DashMap<String, CacheEntry>>,
     /// label -> in-flight mutex for request coalescing
     inflight: Arc<DashMap<String, Arc<Mutex<()>>>>,
+    /// Time per API call
+    api_latency: Arc<Histogram>,
 }
 
 #[derive(Clone)]
@@ -114,6 +117,7 @@ impl HeavenResolver {
                 .expect("Failed to build HTTP client"),
             cache: Arc::new(DashMap::new()),
             inflight: Arc::new(DashMap::new()),
+            api_latency: Arc::new(Histogram::new()),
         }
     }
 
@@ -199,7 +203,10 @@ impl HeavenResolver {
         }
 
         // Fetch from API
-        match self.fetch(&key).await {
+        let started = Instant::now();
+        let fetched = self.fetch(&key).await;
+        self.api_latency.observe(started.elapsed());
+        match fetched {
             Ok(resolved) => {
                 // Determine cache TTL based on status
                 let ttl = match resolved.status {
@@ -229,6 +236,23 @@ impl HeavenResolver {
         }
     }
 
+    /// Number of cached names (including expired, not yet evicted)
+    pub fn cache_len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Drop every cached name so the next lookups hit the API; returns how many were dropped
+    pub fn clear_cache(&self) -> usize {
+        let n = self.cache.len();
+        self.cache.clear();
+        n
+    }
+
+    /// API call latency histogram
+    pub fn api_latency(&self) -> &Histogram {
+        &self.api_latency
+    }
+
     /// Fetch name resolution from the Heaven Names API
     async fn fetch(&self, label: &str) -> Result<Resolved, reqwest::Error> {
         let url = format!(
@@ -375,7 +399,7 @@ fn base_response(request: &Message) -> Message {
     resp.set_recursion_desired(request.recursion_desired());
 
     // Preserve EDNS if present (for larger UDP size, DO bit, etc.)
-    if let Some(edns) = request.edns() {
+    if let Some(edns) = request.extensions() {
         resp.set_edns(edns.clone());
     }
 
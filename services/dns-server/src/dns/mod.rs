@@ -3,4 +3,6 @@
 pub mod server;
 pub mod handler;
 pub mod upstream;
+pub mod cache;
 pub mod heaven;
+pub mod doh;
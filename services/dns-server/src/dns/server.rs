@@ -1,16 +1,27 @@
-//! DNS UDP/TCP server
+//! DNS UDP/TCP server, plus optional DNS-over-TLS (RFC 7858) listener
 
-use super::handler::handle_query;
+use super::handler::{handle_query, handle_query_as};
 use crate::config::Config;
+use crate::users::CachedUser;
 use crate::AppState;
 use anyhow::{Context, Result};
-use std::io::ErrorKind;
+use hickory_proto::op::Message;
+use hickory_proto::serialize::binary::BinDecodable;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::broadcast;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+/// Max time for a DoT client to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// UDP payload limit for clients that send no EDNS OPT record (RFC 1035)
+const MIN_UDP_PAYLOAD: u16 = 512;
 
 pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
     let addr: SocketAddr = state.config.dns_listen.parse()
@@ -20,6 +31,8 @@ pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) ->
 
     tracing::info!("DNS server listening on {}", addr);
 
+    let dot = bind_dot(&state.config).await?;
+
     let udp = Arc::new(udp);
     let mut buf = vec![0u8; 4096];
 
@@ -36,8 +49,10 @@ pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) ->
                     let udp = udp.clone();
                     let state = state.clone();
                     tokio::spawn(async move {
-                        if let Some(resp) = handle_query(&state, pkt, src.ip()).await {
-                            let _ = udp.send_to(&resp, src).await;
+                        if let Some(resp) = handle_query(&state, pkt.clone(), src.ip()).await {
+                            if let Some(resp) = fit_udp(&pkt, resp) {
+                                let _ = udp.send_to(&resp, src).await;
+                            }
                         }
                     });
                 }
@@ -47,7 +62,17 @@ pub async fn run(state: Arc<AppState>, mut shutdown: broadcast::Receiver<()>) ->
                 if let Ok((stream, src)) = result {
                     let state = state.clone();
                     tokio::spawn(async move {
-                        handle_tcp(state, stream, src).await;
+                        handle_tcp(state, stream, src, None).await;
+                    });
+                }
+            }
+            // TLS (DoT)
+            result = accept_opt(dot.as_ref().map(|(l, _)| l)) => {
+                if let (Ok((stream, src)), Some((_, acceptor))) = (result, dot.as_ref()) {
+                    let acceptor = acceptor.clone();
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        handle_tls(state, acceptor, stream, src).await;
                     });
                 }
             }
@@ -131,7 +156,130 @@ fn bind_error(proto: &str, addr: SocketAddr, err: std::io::Error) -> anyhow::Err
     anyhow::anyhow!("Failed to bind {} on {}: {}", proto, addr, err)
 }
 
-async fn handle_tcp(state: Arc<AppState>, mut stream: TcpStream, src: SocketAddr) {
+/// Bind the DoT listener if a certificate and key are configured
+async fn bind_dot(config: &Config) -> Result<Option<(TcpListener, TlsAcceptor)>> {
+    let (Some(cert_path), Some(key_path)) = (&config.dot_cert_path, &config.dot_key_path) else {
+        return Ok(None);
+    };
+
+    let acceptor = load_tls_acceptor(cert_path, key_path)?;
+    let addr: SocketAddr = config.dot_listen.parse()
+        .with_context(|| format!("Invalid DoT listen address: {}", config.dot_listen))?;
+    let listener = TcpListener::bind(&addr).await
+        .map_err(|err| bind_error("DoT", addr, err))?;
+
+    tracing::info!("DNS-over-TLS listening on {} (SNI suffix {})", addr, config.dot_sni_suffix);
+    Ok(Some((listener, acceptor)))
+}
+
+fn load_tls_acceptor(cert_path: &str, key_path: &str) -> Result<TlsAcceptor> {
+    let mut cert_reader = BufReader::new(
+        File::open(cert_path).with_context(|| format!("Failed to open {}", cert_path))?,
+    );
+    let certs = rustls_pemfile::certs(&mut cert_reader)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificates in {}", cert_path))?;
+
+    let mut key_reader = BufReader::new(
+        File::open(key_path).with_context(|| format!("Failed to open {}", key_path))?,
+    );
+    let key = rustls_pemfile::private_key(&mut key_reader)
+        .with_context(|| format!("Failed to parse private key in {}", key_path))?
+        .with_context(|| format!("No private key found in {}", key_path))?;
+
+    let tls = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid DoT certificate/key pair")?;
+
+    Ok(TlsAcceptor::from(Arc::new(tls)))
+}
+
+/// Accept on an optional listener; pends forever when the listener is disabled
+async fn accept_opt(
+    listener: Option<&TcpListener>,
+) -> std::io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    match listener {
+        Some(l) => l.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_tls(
+    state: Arc<AppState>,
+    acceptor: TlsAcceptor,
+    stream: tokio::net::TcpStream,
+    src: SocketAddr,
+) {
+    let stream = match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(s)) => s,
+        Ok(Err(e)) => {
+            tracing::debug!("DoT handshake failed from {}: {}", src, e);
+            return;
+        }
+        Err(_) => {
+            tracing::debug!("DoT handshake timed out from {}", src);
+            return;
+        }
+    };
+
+    // Attribute the connection via the <token>.<suffix> SNI label
+    let user = stream
+        .get_ref()
+        .1
+        .server_name()
+        .and_then(|sni| sni_token(sni, &state.config.dot_sni_suffix))
+        .and_then(|token| state.user_cache.get_by_dot_token(token))
+        .map(|u| CachedUser { vpn_ip: src.ip(), ..u });
+
+    handle_tcp(state, stream, src, user).await;
+}
+
+/// Extract the token label from `<token>.<suffix>`. The suffix matches
+/// case-insensitively like any DNS name; the token is returned as sent.
+fn sni_token<'a>(sni: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = sni.len().checked_sub(suffix.len())?;
+    if !sni.get(split..)?.eq_ignore_ascii_case(suffix) {
+        return None;
+    }
+    let token = sni[..split].strip_suffix('.')?;
+    if token.is_empty() || token.contains('.') {
+        return None;
+    }
+    Some(token)
+}
+
+/// Keep a UDP reply within the client's advertised payload size. Larger
+/// answers (e.g. from an upstream TCP retry) are cut to an empty reply with
+/// TC set, so the client retries over TCP.
+fn fit_udp(query: &[u8], resp: Vec<u8>) -> Option<Vec<u8>> {
+    if resp.len() <= MIN_UDP_PAYLOAD as usize {
+        return Some(resp);
+    }
+    let limit = Message::from_bytes(query)
+        .ok()
+        .and_then(|q| q.extensions().as_ref().map(|e| e.max_payload()))
+        .unwrap_or(MIN_UDP_PAYLOAD)
+        .max(MIN_UDP_PAYLOAD);
+    if resp.len() <= limit as usize {
+        return Some(resp);
+    }
+
+    let mut msg = Message::from_bytes(&resp).ok()?;
+    msg.take_answers();
+    msg.take_name_servers();
+    msg.take_additionals();
+    msg.set_truncated(true);
+    msg.to_vec().ok()
+}
+
+/// Serve length-prefixed DNS messages (RFC 1035 4.2.2) over TCP or TLS.
+/// With no identity, each query is attributed by source IP as over UDP.
+async fn handle_tcp<S>(state: Arc<AppState>, mut stream: S, src: SocketAddr, user: Option<CachedUser>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let _conn = state.metrics.tcp_connection();
     loop {
         // TCP DNS: 2-byte length prefix
         let mut len_buf = [0u8; 2];
@@ -149,7 +297,11 @@ async fn handle_tcp(state: Arc<AppState>, mut stream: TcpStream, src: SocketAddr
             break;
         }
 
-        let Some(resp) = handle_query(&state, msg, src.ip()).await else {
+        let resp = match &user {
+            Some(u) => handle_query_as(&state, msg, src.ip(), Some(u.clone())).await,
+            None => handle_query(&state, msg, src.ip()).await,
+        };
+        let Some(resp) = resp else {
             break;
         };
 
@@ -162,3 +314,55 @@ async fn handle_tcp(state: Arc<AppState>, mut stream: TcpStream, src: SocketAddr
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sni_token() {
+        let suffix = "dns.heaven.computer";
+        assert_eq!(sni_token("abc123.dns.heaven.computer", suffix), Some("abc123"));
+        assert_eq!(sni_token("dns.heaven.computer", suffix), None);
+        assert_eq!(sni_token("a.b.dns.heaven.computer", suffix), None);
+        assert_eq!(sni_token("abc123xdns.heaven.computer", suffix), None);
+        assert_eq!(sni_token("abc123.example.com", suffix), None);
+        assert_eq!(sni_token("AbC123.DNS.Heaven.Computer", suffix), Some("AbC123"));
+    }
+
+    #[test]
+    fn test_fit_udp_truncates_to_client_payload() {
+        use hickory_proto::op::{Edns, MessageType, Query};
+        use hickory_proto::rr::rdata::A;
+        use hickory_proto::rr::{Name, RData, Record, RecordType};
+        use std::net::Ipv4Addr;
+
+        let name = Name::from_ascii("big.example.com.").unwrap();
+        let mut query = Message::new();
+        query.set_id(7).add_query(Query::query(name.clone(), RecordType::A));
+        let mut response = query.clone();
+        response.set_message_type(MessageType::Response);
+        for i in 0..100u8 {
+            response.add_answer(Record::from_rdata(name.clone(), 60, RData::A(A(Ipv4Addr::new(10, 0, 0, i)))));
+        }
+        let resp = response.to_vec().unwrap();
+        assert!(resp.len() > 1232);
+
+        // No EDNS: 512 byte limit
+        let cut = Message::from_vec(&fit_udp(&query.to_vec().unwrap(), resp.clone()).unwrap()).unwrap();
+        assert!(cut.truncated());
+        assert_eq!(cut.id(), 7);
+        assert!(cut.answers().is_empty());
+        assert_eq!(cut.queries().len(), 1);
+
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232);
+        query.set_edns(edns.clone());
+        let cut = Message::from_vec(&fit_udp(&query.to_vec().unwrap(), resp.clone()).unwrap()).unwrap();
+        assert!(cut.truncated() && cut.answers().is_empty());
+
+        edns.set_max_payload(4096);
+        query.set_edns(edns);
+        assert_eq!(fit_udp(&query.to_vec().unwrap(), resp.clone()), Some(resp));
+    }
+}
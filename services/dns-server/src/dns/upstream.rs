@@ -1,24 +1,434 @@
-//! Forward DNS queries to upstream resolver
+//! Forward DNS queries to upstream resolvers
+//!
+//! Long-lived client that multiplexes queries by DNS ID over a small pool of
+//! connected UDP sockets per upstream. Truncated answers (TC bit) are retried
+//! over TCP, and queries fail over across upstreams with simple health
+//! tracking: an upstream that fails repeatedly is skipped for a cooldown.
 
+use crate::metrics::Histogram;
 use anyhow::{Context, Result};
-use std::time::Duration;
-use tokio::net::UdpSocket;
+use dashmap::DashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::oneshot;
 use tokio::time::timeout;
 
-const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest DNS message (UDP with EDNS, or TCP)
+const MAX_MESSAGE_SIZE: usize = 65535;
+/// Consecutive failures before an upstream is marked down
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long a down upstream is skipped before being retried
+const DOWN_COOLDOWN: Duration = Duration::from_secs(30);
+/// TC (truncated) flag in the third header byte
+const TC_FLAG: u8 = 0x02;
+/// Random picks before giving up on finding a free DNS ID on a socket
+const ID_ATTEMPTS: usize = 64;
 
-pub async fn forward(upstream: &str, query: &[u8]) -> Result<Vec<u8>> {
-    // Use UDP for simplicity; add TCP fallback if needed
-    let socket = UdpSocket::bind("0.0.0.0:0").await
-        .context("Failed to bind UDP socket for upstream")?;
+/// Pooled upstream DNS client with TCP fallback and failover
+pub struct UpstreamClient {
+    servers: Vec<Upstream>,
+    timeout: Duration,
+    /// Reference point for the `down_until` timestamps
+    epoch: Instant,
+    /// Time per `forward`, including failover
+    latency: Histogram,
+}
+
+struct Upstream {
+    addr: SocketAddr,
+    sockets: Vec<Arc<PooledSocket>>,
+    next_socket: AtomicUsize,
+    consecutive_failures: AtomicU32,
+    /// Millis since `epoch` until which this upstream is skipped (0 = healthy)
+    down_until_ms: AtomicU64,
+}
+
+/// A connected UDP socket with in-flight queries keyed by (rewritten) DNS ID
+struct PooledSocket {
+    socket: UdpSocket,
+    pending: DashMap<u16, PendingQuery>,
+}
+
+/// An in-flight query: answers must echo its question to be accepted
+struct PendingQuery {
+    question: Vec<u8>,
+    tx: oneshot::Sender<Vec<u8>>,
+}
+
+/// Releases a reserved DNS ID when its query completes, fails or is dropped
+struct PendingGuard<'a> {
+    pending: &'a DashMap<u16, PendingQuery>,
+    id: u16,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.remove(&self.id);
+    }
+}
+
+impl UpstreamClient {
+    /// Bind `sockets_per_upstream` UDP sockets for each upstream and start
+    /// their receive loops. Must be called from within a Tokio runtime.
+    pub async fn connect(
+        upstreams: &[SocketAddr],
+        sockets_per_upstream: usize,
+        query_timeout: Duration,
+    ) -> Result<Self> {
+        anyhow::ensure!(!upstreams.is_empty(), "No upstream DNS servers configured");
+
+        let mut servers = Vec::with_capacity(upstreams.len());
+        for &addr in upstreams {
+            let mut sockets = Vec::with_capacity(sockets_per_upstream.max(1));
+            for _ in 0..sockets_per_upstream.max(1) {
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    "0.0.0.0:0".parse()?
+                } else {
+                    "[::]:0".parse()?
+                };
+                let socket = UdpSocket::bind(bind).await
+                    .context("Failed to bind UDP socket for upstream")?;
+                socket.connect(addr).await
+                    .with_context(|| format!("Failed to connect UDP socket to {}", addr))?;
+
+                let pooled = Arc::new(PooledSocket {
+                    socket,
+                    pending: DashMap::new(),
+                });
+                tokio::spawn(recv_loop(pooled.clone()));
+                sockets.push(pooled);
+            }
+
+            servers.push(Upstream {
+                addr,
+                sockets,
+                next_socket: AtomicUsize::new(0),
+                consecutive_failures: AtomicU32::new(0),
+                down_until_ms: AtomicU64::new(0),
+            });
+        }
+
+        Ok(Self {
+            servers,
+            timeout: query_timeout,
+            epoch: Instant::now(),
+            latency: Histogram::new(),
+        })
+    }
+
+    /// Forward a query, failing over across upstreams until one answers
+    pub async fn forward(&self, query: &[u8]) -> Result<Vec<u8>> {
+        anyhow::ensure!(query.len() >= 12, "Query shorter than DNS header");
+        let started = Instant::now();
+        let result = self.forward_inner(query).await;
+        self.latency.observe(started.elapsed());
+        result
+    }
+
+    async fn forward_inner(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let now_ms = self.epoch.elapsed().as_millis() as u64;
+
+        // Healthy upstreams first (in configured order), then down ones as a last resort
+        let (healthy, down): (Vec<&Upstream>, Vec<&Upstream>) = self
+            .servers
+            .iter()
+            .partition(|u| u.down_until_ms.load(Ordering::Relaxed) <= now_ms);
+
+        let mut last_error = None;
+        for upstream in healthy.into_iter().chain(down) {
+            match self.query_upstream(upstream, query).await {
+                Ok(resp) => {
+                    upstream.consecutive_failures.store(0, Ordering::Relaxed);
+                    upstream.down_until_ms.store(0, Ordering::Relaxed);
+                    return Ok(resp);
+                }
+                Err(e) => {
+                    self.record_failure(upstream);
+                    tracing::debug!("Upstream {} failed: {}", upstream.addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No upstream available")))
+    }
+
+    /// Forward latency histogram
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Number of upstreams currently marked down
+    pub fn down_count(&self) -> usize {
+        let now_ms = self.epoch.elapsed().as_millis() as u64;
+        self.servers
+            .iter()
+            .filter(|u| u.down_until_ms.load(Ordering::Relaxed) > now_ms)
+            .count()
+    }
+
+    async fn query_upstream(&self, upstream: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
+        let idx = upstream.next_socket.fetch_add(1, Ordering::Relaxed) % upstream.sockets.len();
+        let resp = query_udp(&upstream.sockets[idx], query, self.timeout).await?;
+
+        if resp.len() > 2 && resp[2] & TC_FLAG != 0 {
+            tracing::debug!("Truncated answer from {}, retrying over TCP", upstream.addr);
+            return query_tcp(upstream.addr, query, self.timeout).await;
+        }
+
+        Ok(resp)
+    }
+
+    fn record_failure(&self, upstream: &Upstream) {
+        let failures = upstream.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= FAILURE_THRESHOLD {
+            let until = (self.epoch.elapsed() + DOWN_COOLDOWN).as_millis() as u64;
+            if upstream.down_until_ms.swap(until, Ordering::Relaxed) == 0 {
+                tracing::warn!(
+                    "Upstream {} marked down after {} consecutive failures",
+                    upstream.addr,
+                    failures
+                );
+            }
+        }
+    }
+}
+
+/// Send over a pooled socket under a fresh DNS ID, restoring the client's ID on the answer
+async fn query_udp(pooled: &PooledSocket, query: &[u8], query_timeout: Duration) -> Result<Vec<u8>> {
+    let original_id = [query[0], query[1]];
+    let question = question_section(query).context("Query has no question")?.to_vec();
 
-    socket.send_to(query, upstream).await
-        .context("Failed to send to upstream")?;
+    // Reserve an ID not currently in flight on this socket
+    let (tx, rx) = oneshot::channel();
+    let mut entry = Some(PendingQuery { question, tx });
+    let mut reserved = None;
+    for _ in 0..ID_ATTEMPTS {
+        let candidate: u16 = rand::random();
+        if let dashmap::mapref::entry::Entry::Vacant(slot) = pooled.pending.entry(candidate) {
+            slot.insert(entry.take().expect("inserted at most once"));
+            reserved = Some(candidate);
+            break;
+        }
+    }
+    let id = reserved.context("No free DNS ID on upstream socket")?;
+    // Timed-out, failed or cancelled queries must not keep their ID
+    let _guard = PendingGuard {
+        pending: &pooled.pending,
+        id,
+    };
 
-    let mut buf = vec![0u8; 4096];
-    let n = timeout(UPSTREAM_TIMEOUT, socket.recv(&mut buf)).await
+    let mut out = query.to_vec();
+    out[..2].copy_from_slice(&id.to_be_bytes());
+
+    pooled.socket.send(&out).await.context("Failed to send to upstream")?;
+
+    let mut resp = timeout(query_timeout, rx)
+        .await
         .context("Upstream timeout")?
-        .context("Failed to receive from upstream")?;
+        .context("Upstream receive loop stopped")?;
+    resp[..2].copy_from_slice(&original_id);
+    Ok(resp)
+}
+
+/// Dispatch responses on a pooled socket to their waiting queries. An answer
+/// whose question differs (e.g. a late reply to an earlier query that used
+/// the same ID) is dropped and the query keeps waiting.
+async fn recv_loop(pooled: Arc<PooledSocket>) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        match pooled.socket.recv(&mut buf).await {
+            Ok(n) if n >= 12 => {
+                let resp = &buf[..n];
+                let id = u16::from_be_bytes([resp[0], resp[1]]);
+                let answered = question_section(resp);
+                let matched = pooled.pending.remove_if(&id, |_, p| {
+                    answered.is_some_and(|q| same_question(&p.question, q))
+                });
+                match matched {
+                    Some((_, p)) => {
+                        let _ = p.tx.send(resp.to_vec());
+                    }
+                    None if pooled.pending.contains_key(&id) => {
+                        tracing::debug!("Dropping upstream answer with mismatched question (id {})", id);
+                    }
+                    None => {}
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // ICMP port unreachable surfaces here on connected sockets; keep going
+                tracing::debug!("Upstream socket recv error: {}", e);
+            }
+        }
+    }
+}
+
+/// The first question (QNAME, QTYPE, QCLASS) of a DNS message, as raw bytes
+fn question_section(msg: &[u8]) -> Option<&[u8]> {
+    if msg.len() < 12 || u16::from_be_bytes([msg[4], msg[5]]) == 0 {
+        return None;
+    }
+    let mut pos = 12;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xc0 != 0 {
+            // Compression pointer ends the name
+            pos += 2;
+            break;
+        }
+        pos += 1 + len;
+    }
+    msg.get(12..pos + 4)
+}
+
+/// Questions match if type and class are equal and names differ at most in case
+fn same_question(a: &[u8], b: &[u8]) -> bool {
+    let (Some(split_a), Some(split_b)) = (a.len().checked_sub(4), b.len().checked_sub(4)) else {
+        return false;
+    };
+    a[split_a..] == b[split_b..] && a[..split_a].eq_ignore_ascii_case(&b[..split_b])
+}
+
+/// One-shot TCP query (RFC 1035 4.2.2 length-prefixed framing)
+async fn query_tcp(addr: SocketAddr, query: &[u8], query_timeout: Duration) -> Result<Vec<u8>> {
+    timeout(query_timeout, async {
+        let mut stream = TcpStream::connect(addr).await
+            .with_context(|| format!("Failed to connect TCP to {}", addr))?;
+
+        let len = u16::try_from(query.len()).context("Query too large for TCP")?;
+        stream.write_all(&len.to_be_bytes()).await?;
+        stream.write_all(query).await?;
+
+        let mut len_buf = [0u8; 2];
+        stream.read_exact(&mut len_buf).await?;
+        let mut resp = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut resp).await?;
+        Ok(resp)
+    })
+    .await
+    .context("Upstream TCP timeout")?
+}
+
+/// Parse a comma-separated UPSTREAM_DNS list
+pub fn parse_upstreams(s: &str) -> Result<Vec<SocketAddr>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| a.parse().with_context(|| format!("Invalid upstream DNS address: {}", a)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Fake upstream answering over UDP with TC set and over TCP with the full reply
+    async fn truncating_upstream() -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (n, src) = udp.recv_from(&mut buf).await.unwrap();
+                let mut resp = buf[..n].to_vec();
+                resp[2] |= 0x80 | TC_FLAG;
+                udp.send_to(&resp, src).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut msg).await.unwrap();
+            msg[2] |= 0x80;
+            msg.extend_from_slice(b"full");
+            stream.write_all(&(msg.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&msg).await.unwrap();
+        });
+
+        addr
+    }
+
+    /// `<label>.` A/IN query with ID 0xabcd
+    fn query_for(label: &[u8]) -> Vec<u8> {
+        let mut query = vec![0xab, 0xcd, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.push(label.len() as u8);
+        query.extend_from_slice(label);
+        query.extend_from_slice(&[0, 0, 1, 0, 1]);
+        query
+    }
+
+    #[tokio::test]
+    async fn test_truncated_answer_retried_over_tcp_after_failover() {
+        // Nothing listens on the first upstream, so the client must fail over
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let live = truncating_upstream().await;
+
+        let client = UpstreamClient::connect(&[dead, live], 2, Duration::from_millis(200))
+            .await
+            .unwrap();
+
+        let resp = client.forward(&query_for(b"example")).await.unwrap();
+
+        assert_eq!(&resp[..2], &[0xab, 0xcd]);
+        assert_eq!(resp[2] & TC_FLAG, 0);
+        assert!(resp.ends_with(b"full"));
+    }
+
+    #[tokio::test]
+    async fn test_answer_must_match_question_and_ids_are_released() {
+        // Upstream answering every query with a different name under the same ID
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (n, src) = udp.recv_from(&mut buf).await.unwrap();
+                let mut resp = buf[..n].to_vec();
+                resp[2] |= 0x80;
+                resp[13..20].copy_from_slice(b"EXAMPLE");
+                udp.send_to(&resp, src).await.unwrap();
+            }
+        });
+
+        let client = UpstreamClient::connect(&[addr], 1, Duration::from_millis(200))
+            .await
+            .unwrap();
+
+        // Same name in another case is accepted
+        let resp = client.forward(&query_for(b"example")).await.unwrap();
+        assert_eq!(&resp[13..20], b"EXAMPLE");
+        // A different name is not delivered, so the query times out
+        assert!(client.forward(&query_for(b"another")).await.is_err());
+
+        // A cancelled query gives its ID back
+        let pending = &client.servers[0].sockets[0].pending;
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(20),
+            client.forward(&query_for(b"another")),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert!(pending.is_empty());
+    }
 
-    Ok(buf[..n].to_vec())
+    #[test]
+    fn test_parse_upstreams() {
+        let addrs = parse_upstreams("127.0.0.1:5353, 10.0.0.1:53").unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert!(parse_upstreams("not-an-addr").is_err());
+    }
 }
@@ -0,0 +1,180 @@
+//! Aggregated interest profiles (INGEST_MODE=aggregate)
+//!
+//! Instead of shipping one row per query, keeps per-wallet category weight
+//! counters in memory and periodically emits one histogram row per
+//! (wallet, category) for the window. Timestamps, domains and devices never
+//! leave the server, and Tinybird volume drops to a few rows per user.
+//!
+//! With AGGREGATE_DP_EPSILON set, every wallet seen in the window gets a
+//! row for every category in `Category::ALL`, zero cells included, each with
+//! Laplace noise of scale 1/epsilon (clamped at 0). One query contributes at
+//! most 1 in total across a wallet's categories (weights sum to 1), so the
+//! histogram is epsilon-DP per query, not per user: heavy browsing of one
+//! category still shows through. That a wallet was active in the window at
+//! all is not protected.
+
+use super::privacy::REDACTED;
+use super::DnsEvent;
+use crate::categorize::Category;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Wallet placeholder for queries from unknown source IPs
+const UNKNOWN_WALLET: &str = "unknown";
+
+/// One (wallet, category) cell of an emitted histogram
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryHistogram {
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub wallet_id: String,
+    pub category_id: i32,
+    /// Summed category weights over the window (noised when epsilon is set)
+    pub weight: f64,
+    /// Privacy budget the cell was noised with (None = exact)
+    pub epsilon: Option<f64>,
+}
+
+struct Window {
+    start: DateTime<Utc>,
+    /// wallet -> category_id -> summed weight
+    counts: HashMap<String, HashMap<i32, f64>>,
+}
+
+/// Rolling per-wallet category counters
+pub struct InterestAggregator {
+    window: Mutex<Window>,
+    epsilon: Option<f64>,
+}
+
+impl InterestAggregator {
+    pub fn new(epsilon: Option<f64>) -> Self {
+        Self {
+            window: Mutex::new(Window {
+                start: Utc::now(),
+                counts: HashMap::new(),
+            }),
+            epsilon: epsilon.filter(|e| *e > 0.0),
+        }
+    }
+
+    /// Add an event's categories to its wallet's counters. Events without a
+    /// wallet (unknown source, or redacted by the privacy policy) or without
+    /// categories carry no profile signal and are ignored.
+    pub fn record(&self, event: &DnsEvent) {
+        if event.categories.is_empty() || event.wallet_id == UNKNOWN_WALLET || event.wallet_id == REDACTED {
+            return;
+        }
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let cells = window.counts.entry(event.wallet_id.clone()).or_default();
+        for c in &event.categories {
+            *cells.entry(c.category_id).or_default() += c.weight as f64;
+        }
+    }
+
+    /// Number of wallets with counters in the current window
+    pub fn wallets(&self) -> usize {
+        self.window.lock().unwrap_or_else(|e| e.into_inner()).counts.len()
+    }
+
+    /// Close the current window and return its (optionally noised) histogram
+    pub fn drain(&self) -> Vec<CategoryHistogram> {
+        let now = Utc::now();
+        let window = {
+            let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+            std::mem::replace(
+                &mut *window,
+                Window {
+                    start: now,
+                    counts: HashMap::new(),
+                },
+            )
+        };
+
+        let mut rows = Vec::new();
+        for (wallet_id, cells) in window.counts {
+            let mut push = |category_id, weight| {
+                rows.push(CategoryHistogram {
+                    window_start: window.start,
+                    window_end: now,
+                    wallet_id: wallet_id.clone(),
+                    category_id,
+                    weight,
+                    epsilon: self.epsilon,
+                })
+            };
+            match self.epsilon {
+                // Noise the full histogram so which categories appear reveals nothing
+                Some(eps) => {
+                    for category in Category::ALL {
+                        let id = category as i32;
+                        let weight = cells.get(&id).copied().unwrap_or(0.0);
+                        push(id, (weight + laplace(1.0 / eps)).max(0.0));
+                    }
+                }
+                None => {
+                    for (category_id, weight) in cells {
+                        push(category_id, weight);
+                    }
+                }
+            }
+        }
+        rows
+    }
+}
+
+/// Sample Laplace(0, scale) by inverse CDF
+fn laplace(scale: f64) -> f64 {
+    let u: f64 = rand::random::<f64>() - 0.5;
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::test_event;
+    use crate::categorize::CategoryWeight;
+
+    fn event(wallet: &str, categories: &[(i32, f32)]) -> DnsEvent {
+        DnsEvent {
+            wallet_id: wallet.to_string(),
+            categories: categories
+                .iter()
+                .map(|&(category_id, weight)| CategoryWeight { category_id, weight })
+                .collect(),
+            ..test_event()
+        }
+    }
+
+    #[test]
+    fn test_exact_histogram() {
+        let agg = InterestAggregator::new(None);
+        agg.record(&event("0xa", &[(3, 1.0)]));
+        agg.record(&event("0xa", &[(3, 0.5), (1, 0.5)]));
+        agg.record(&event("0xb", &[(9, 1.0)]));
+        agg.record(&event(UNKNOWN_WALLET, &[(9, 1.0)]));
+        agg.record(&event(REDACTED, &[(100, 1.0)]));
+        agg.record(&event("0xb", &[]));
+        assert_eq!(agg.wallets(), 2);
+
+        let mut rows = agg.drain();
+        rows.sort_by_key(|r| (r.wallet_id.clone(), r.category_id));
+        let cells: Vec<_> = rows.iter().map(|r| (r.wallet_id.as_str(), r.category_id, r.weight)).collect();
+        assert_eq!(cells, vec![("0xa", 1, 0.5), ("0xa", 3, 1.5), ("0xb", 9, 1.0)]);
+        assert!(agg.drain().is_empty());
+    }
+
+    #[test]
+    fn test_noised_histogram_covers_every_category() {
+        let agg = InterestAggregator::new(Some(0.5));
+        for _ in 0..50 {
+            agg.record(&event("0xa", &[(3, 1.0)]));
+        }
+        let rows = agg.drain();
+        assert_eq!(rows.len(), Category::ALL.len());
+        assert!(Category::ALL.iter().all(|&c| rows.iter().any(|r| r.category_id == c as i32)));
+        assert!(rows.iter().all(|r| r.weight >= 0.0 && r.epsilon == Some(0.5)));
+    }
+}
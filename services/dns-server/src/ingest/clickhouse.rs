@@ -0,0 +1,127 @@
+//! ClickHouse sink over the HTTP interface (`INSERT ... FORMAT JSONEachRow`)
+//!
+//! Expected tables (same columns as the Tinybird datasources):
+//!
+//! ```sql
+//! CREATE TABLE dns_events (
+//!     ts DateTime64(3), wallet_id String, device_id UUID, etld1 String,
+//!     domain_hmac String, key_id LowCardinality(String), qtype LowCardinality(String),
+//!     action LowCardinality(String),
+//!     category_ids Array(Int32), category_weights Array(Float32), latency_ms UInt32,
+//!     count UInt32, session_id Nullable(UUID), session_start Bool, infra Bool
+//! ) ENGINE = MergeTree PARTITION BY toYYYYMM(ts) ORDER BY (wallet_id, ts);
+//!
+//! CREATE TABLE interest_histograms (
+//!     window_start DateTime64(3), window_end DateTime64(3), wallet_id String,
+//!     category_id Int32, weight Float64, epsilon Nullable(Float64)
+//! ) ENGINE = MergeTree ORDER BY (wallet_id, window_start);
+//! ```
+
+use super::aggregate::CategoryHistogram;
+use super::{ndjson, sink_http_client, DnsEvent, EventSink};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// `DnsEvent` with categories flattened into parallel arrays
+#[derive(Serialize)]
+struct EventRow<'a> {
+    ts: DateTime<Utc>,
+    wallet_id: &'a str,
+    device_id: Uuid,
+    etld1: &'a str,
+    domain_hmac: &'a str,
+    key_id: &'a str,
+    qtype: &'a str,
+    action: &'a str,
+    category_ids: Vec<i32>,
+    category_weights: Vec<f32>,
+    latency_ms: u32,
+    count: u32,
+    session_id: Option<Uuid>,
+    session_start: bool,
+    infra: bool,
+}
+
+impl<'a> From<&'a DnsEvent> for EventRow<'a> {
+    fn from(e: &'a DnsEvent) -> Self {
+        Self {
+            ts: e.ts,
+            wallet_id: &e.wallet_id,
+            device_id: e.device_id,
+            etld1: &e.etld1,
+            domain_hmac: &e.domain_hmac,
+            key_id: &e.key_id,
+            qtype: &e.qtype,
+            action: &e.action,
+            category_ids: e.categories.iter().map(|c| c.category_id).collect(),
+            category_weights: e.categories.iter().map(|c| c.weight).collect(),
+            latency_ms: e.latency_ms,
+            count: e.count,
+            session_id: e.session_id,
+            session_start: e.session_start,
+            infra: e.infra,
+        }
+    }
+}
+
+pub struct ClickHouseSink {
+    url: String,
+    database: String,
+    user: Option<String>,
+    password: Option<String>,
+    client: reqwest::Client,
+}
+
+impl ClickHouseSink {
+    pub fn new(url: &str, database: &str, user: Option<String>, password: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            database: database.to_string(),
+            user,
+            password,
+            client: sink_http_client(),
+        }
+    }
+
+    async fn insert(&self, table: &str, body: String) -> anyhow::Result<()> {
+        let query = format!("INSERT INTO {}.{} FORMAT JSONEachRow", self.database, table);
+        let mut req = self
+            .client
+            .post(format!("{}/", self.url))
+            .query(&[("query", query.as_str()), ("date_time_input_format", "best_effort")])
+            .body(body);
+        if let Some(user) = &self.user {
+            req = req.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            req = req.header("X-ClickHouse-Key", password);
+        }
+
+        let resp = req.send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("{} - {}", status, body.trim());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for ClickHouseSink {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+        let rows: Vec<EventRow> = events.iter().map(EventRow::from).collect();
+        self.insert("dns_events", ndjson(&rows)?).await
+    }
+
+    async fn send_histograms(&self, rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+        self.insert("interest_histograms", ndjson(rows)?).await
+    }
+}
@@ -0,0 +1,238 @@
+//! Query deduplication and browsing sessions
+//!
+//! One page load fans out into dozens of lookups for the same site, and
+//! polling apps repeat theirs every few seconds. Events are held for
+//! DEDUP_WINDOW_SECS keyed on (wallet, device, eTLD+1, qtype, action, infra,
+//! primary category); repeats in the window only bump the first event's
+//! `count`. The wallet is part of the key because DoH events share the nil
+//! device id. Action, infra and category are part of it because names under
+//! one eTLD+1 can be blocked, infra or categorized differently
+//! (`ads.example.com` vs `www.example.com`, `music.apple.com` vs `apple.com`).
+//!
+//! Released events are then grouped into visits per (wallet, device, eTLD+1):
+//! a lookup more than SESSION_GAP_SECS after the previous one starts a new
+//! session. Scoring counts `session_start` events, i.e. visits.
+//!
+//! Events without an eTLD+1 (redacted or categories-only) have no site to
+//! group or sessionize by, so they pass through unchanged; the queue then
+//! counts each as its own visit, as it does every event when dedup is off.
+
+use super::DnsEvent;
+use crate::categorize::primary_category;
+use chrono::{DateTime, Duration, Utc};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+/// Max groups held at once; beyond this events pass through undeduplicated
+const MAX_PENDING: usize = 10_000;
+
+/// Events with equal keys collapse into one
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DedupKey {
+    wallet_id: String,
+    device_id: Uuid,
+    etld1: String,
+    qtype: String,
+    action: String,
+    infra: bool,
+    category: Option<i32>,
+}
+
+impl DedupKey {
+    fn of(event: &DnsEvent) -> Self {
+        Self {
+            wallet_id: event.wallet_id.clone(),
+            device_id: event.device_id,
+            etld1: event.etld1.clone(),
+            qtype: event.qtype.clone(),
+            action: event.action.clone(),
+            infra: event.infra,
+            category: primary_category(&event.categories),
+        }
+    }
+}
+/// (wallet_id, device_id, etld1)
+type SessionKey = (String, Uuid, String);
+
+struct Pending {
+    event: DnsEvent,
+    /// Time of the latest repeat
+    last: DateTime<Utc>,
+}
+
+struct Session {
+    id: Uuid,
+    last_seen: DateTime<Utc>,
+}
+
+pub struct Deduplicator {
+    window: Duration,
+    session_gap: Duration,
+    pending: DashMap<DedupKey, Pending>,
+    /// Entries in `pending`, tracked separately since `len()` locks every shard
+    pending_len: AtomicUsize,
+    sessions: DashMap<SessionKey, Session>,
+}
+
+impl Deduplicator {
+    pub fn new(window_secs: u64, session_gap_secs: u64) -> Self {
+        Self {
+            window: Duration::seconds(window_secs as i64),
+            session_gap: Duration::seconds(session_gap_secs as i64),
+            pending: DashMap::new(),
+            pending_len: AtomicUsize::new(0),
+            sessions: DashMap::new(),
+        }
+    }
+
+    /// Hold an event for deduplication. Returns it back if it bypasses the
+    /// window (no eTLD+1, or too many groups pending).
+    pub fn offer(&self, event: DnsEvent) -> Option<DnsEvent> {
+        if event.etld1.is_empty() {
+            return Some(event);
+        }
+        let full = self.pending_len.load(Ordering::Relaxed) >= MAX_PENDING;
+        match self.pending.entry(DedupKey::of(&event)) {
+            Entry::Occupied(mut e) => {
+                let pending = e.get_mut();
+                pending.event.count += event.count;
+                pending.last = pending.last.max(event.ts);
+                None
+            }
+            Entry::Vacant(_) if full => Some(event),
+            Entry::Vacant(v) => {
+                let last = event.ts;
+                v.insert(Pending { event, last });
+                self.pending_len.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Release groups whose window has closed (every group if `all`), with
+    /// session fields set, oldest first
+    pub fn release(&self, now: DateTime<Utc>, all: bool) -> Vec<DnsEvent> {
+        let cutoff = now - self.window;
+        let closed = |p: &Pending| all || p.event.ts <= cutoff;
+
+        let keys: Vec<DedupKey> = self
+            .pending
+            .iter()
+            .filter(|e| closed(e.value()))
+            .map(|e| e.key().clone())
+            .collect();
+        let mut released: Vec<Pending> = keys
+            .into_iter()
+            .filter_map(|k| self.pending.remove_if(&k, |_, p| closed(p)).map(|(_, p)| p))
+            .collect();
+        self.pending_len.fetch_sub(released.len(), Ordering::Relaxed);
+        released.sort_by_key(|p| p.event.ts);
+
+        let events = released
+            .into_iter()
+            .map(|Pending { mut event, last }| {
+                self.sessionize(&mut event, last);
+                event
+            })
+            .collect();
+
+        // Forget sessions that can no longer be continued
+        self.sessions.retain(|_, s| now - s.last_seen <= self.session_gap);
+        events
+    }
+
+    /// Attach the event to its visit, starting a new one after a long gap
+    fn sessionize(&self, event: &mut DnsEvent, last: DateTime<Utc>) {
+        let key = (event.wallet_id.clone(), event.device_id, event.etld1.clone());
+        let mut session = self.sessions.entry(key).or_insert_with(|| Session {
+            id: Uuid::nil(),
+            last_seen: event.ts,
+        });
+        if session.id.is_nil() || event.ts - session.last_seen > self.session_gap {
+            session.id = Uuid::new_v4();
+            event.session_start = true;
+        }
+        session.last_seen = session.last_seen.max(last);
+        event.session_id = Some(session.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::categorize::CategoryWeight;
+    use crate::ingest::test_event;
+
+    fn event(etld1: &str, qtype: &str, ts: DateTime<Utc>) -> DnsEvent {
+        DnsEvent {
+            ts,
+            etld1: etld1.to_string(),
+            qtype: qtype.to_string(),
+            ..test_event()
+        }
+    }
+
+    #[test]
+    fn test_collapses_repeats_in_window() {
+        let dedup = Deduplicator::new(10, 1800);
+        let t0 = Utc::now();
+        for i in 0..5 {
+            assert!(dedup.offer(event("example.com", "A", t0 + Duration::seconds(i))).is_none());
+        }
+        assert!(dedup.offer(event("example.com", "AAAA", t0)).is_none());
+        // Redacted events pass straight through
+        assert!(dedup.offer(event("", "A", t0)).is_some());
+
+        assert!(dedup.release(t0 + Duration::seconds(5), false).is_empty());
+        let released = dedup.release(t0 + Duration::seconds(10), false);
+        let mut counts: Vec<_> = released.iter().map(|e| (e.qtype.as_str(), e.count)).collect();
+        counts.sort();
+        assert_eq!(counts, vec![("A", 5), ("AAAA", 1)]);
+    }
+
+    #[test]
+    fn test_differing_outcomes_are_not_merged() {
+        let dedup = Deduplicator::new(10, 1800);
+        let t0 = Utc::now();
+        let music = vec![CategoryWeight { category_id: 3, weight: 1.0 }];
+
+        dedup.offer(event("example.com", "A", t0));
+        dedup.offer(DnsEvent { action: "block".to_string(), ..event("example.com", "A", t0) });
+        dedup.offer(DnsEvent { infra: true, ..event("example.com", "A", t0) });
+        dedup.offer(DnsEvent { categories: music.clone(), ..event("example.com", "A", t0) });
+        dedup.offer(DnsEvent { categories: music, ..event("example.com", "A", t0) });
+        assert_eq!(dedup.pending.len(), 4);
+
+        let released = dedup.release(t0 + Duration::seconds(10), false);
+        assert_eq!(released.len(), 4);
+        assert_eq!(released.iter().map(|e| e.count).sum::<u32>(), 5);
+        assert!(dedup.pending.is_empty());
+        assert_eq!(dedup.pending_len.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_sessions_split_on_gap() {
+        let dedup = Deduplicator::new(10, 1800);
+        let t0 = Utc::now();
+
+        dedup.offer(event("example.com", "A", t0));
+        dedup.offer(event("example.com", "AAAA", t0 + Duration::seconds(1)));
+        let first = dedup.release(t0 + Duration::seconds(20), false);
+        assert_eq!(first.len(), 2);
+        assert!(first[0].session_start && !first[1].session_start);
+        assert_eq!(first[0].session_id, first[1].session_id);
+
+        // Same visit ten minutes later; a new one after an hour
+        dedup.offer(event("example.com", "A", t0 + Duration::minutes(10)));
+        let later = dedup.release(t0 + Duration::minutes(11), false);
+        assert!(!later[0].session_start);
+        assert_eq!(later[0].session_id, first[0].session_id);
+
+        dedup.offer(event("example.com", "A", t0 + Duration::minutes(70)));
+        let next = dedup.release(t0 + Duration::minutes(71), true);
+        assert!(next[0].session_start);
+        assert_ne!(next[0].session_id, first[0].session_id);
+    }
+}
@@ -0,0 +1,123 @@
+//! NDJSON file sink for local development (no credentials needed)
+//!
+//! Appends to `<dir>/dns_events.ndjson` and `<dir>/interest_histograms.ndjson`.
+//! A file that would grow past the size limit is renamed to
+//! `<stream>.<timestamp>.ndjson` first, and only the newest `keep` rotated
+//! files per stream are kept.
+
+use super::aggregate::CategoryHistogram;
+use super::{ndjson, DnsEvent, EventSink};
+use axum::async_trait;
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub struct FileSink {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    /// Serializes append + rotate
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(dir: &str, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            max_bytes,
+            keep,
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn append(&self, stream: &str, ndjson: String) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().await;
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let path = self.dir.join(format!("{}.ndjson", stream));
+        let size = tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + ndjson.len() as u64 + 1 > self.max_bytes {
+            self.rotate(stream, &path).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(ndjson.as_bytes()).await?;
+        file.write_all(b"\n").await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn rotate(&self, stream: &str, path: &Path) -> anyhow::Result<()> {
+        let rotated = self.dir.join(format!(
+            "{}.{}.ndjson",
+            stream,
+            Utc::now().format("%Y%m%dT%H%M%S%.9f")
+        ));
+        tokio::fs::rename(path, &rotated).await?;
+
+        // Timestamped names sort chronologically
+        let prefix = format!("{}.", stream);
+        let current = format!("{}.ndjson", stream);
+        let mut old = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix) && name.ends_with(".ndjson") && name != current {
+                old.push(name);
+            }
+        }
+        old.sort();
+        let excess = old.len().saturating_sub(self.keep);
+        for name in &old[..excess] {
+            tokio::fs::remove_file(self.dir.join(name)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+        self.append("dns_events", ndjson(events)?).await
+    }
+
+    async fn send_histograms(&self, rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+        self.append("interest_histograms", ndjson(rows)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::test_event;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_append_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("hp-dns-gw-events-{}", Uuid::new_v4()));
+        let sink = FileSink::new(dir.to_str().unwrap(), 300, 2);
+
+        for _ in 0..8 {
+            sink.send_events(&[test_event()]).await.unwrap();
+        }
+
+        let current = std::fs::read_to_string(dir.join("dns_events.ndjson")).unwrap();
+        assert!(current.lines().all(|l| l.contains("\"wallet_id\":\"0xabc\"")));
+        let rotated = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name() != "dns_events.ndjson")
+            .count();
+        assert_eq!(rotated, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
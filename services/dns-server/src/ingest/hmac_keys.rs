@@ -0,0 +1,156 @@
+//! Versioned HMAC keys for `domain_hmac`
+//!
+//! Every event records the `key_id` its HMAC was computed with, so keys can
+//! rotate without making history unreadable: HMACs only compare within one
+//! key_id. HMAC_SECRET is key `0`; HMAC_KEYS adds more, each optionally
+//! scheduled to take over at a given time. With HMAC_EPOCH_SECS set, the
+//! active key is further derived per epoch (key_id `<id>.<epoch>`), so a
+//! domain's HMAC in one epoch can't be joined against another epoch's.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key id of HMAC_SECRET
+const DEFAULT_KEY_ID: &str = "0";
+
+#[derive(Debug, Clone)]
+struct HmacKey {
+    id: String,
+    secret: Vec<u8>,
+    /// Takes over from earlier keys at this time (None = always active)
+    active_from: Option<DateTime<Utc>>,
+}
+
+/// All configured keys, ordered by activation
+#[derive(Debug, Clone)]
+pub struct HmacKeyring {
+    keys: Vec<HmacKey>,
+    epoch_secs: Option<i64>,
+}
+
+impl HmacKeyring {
+    /// Build from HMAC_SECRET plus HMAC_KEYS: comma-separated `id=secret` or
+    /// `id@<RFC 3339 time>=secret` entries
+    pub fn new(default_secret: &str, keys: Option<&str>, epoch_secs: Option<u64>) -> anyhow::Result<Self> {
+        let mut parsed = vec![HmacKey {
+            id: DEFAULT_KEY_ID.to_string(),
+            secret: default_secret.as_bytes().to_vec(),
+            active_from: None,
+        }];
+        let mut ids: HashSet<String> = HashSet::from([DEFAULT_KEY_ID.to_string()]);
+
+        for entry in keys.unwrap_or("").split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (head, secret) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid HMAC key entry (want id=secret): {}", entry))?;
+            let (id, active_from) = match head.split_once('@') {
+                Some((id, at)) => {
+                    let at = DateTime::parse_from_rfc3339(at.trim())
+                        .map_err(|e| anyhow::anyhow!("Invalid activation time for HMAC key {}: {}", id, e))?;
+                    (id.trim(), Some(at.with_timezone(&Utc)))
+                }
+                None => (head.trim(), None),
+            };
+            if id.is_empty() || id.contains('.') {
+                anyhow::bail!("Invalid HMAC key id (non-empty, no '.'): {:?}", id);
+            }
+            if secret.is_empty() {
+                anyhow::bail!("Empty secret for HMAC key {}", id);
+            }
+            if !ids.insert(id.to_string()) {
+                anyhow::bail!("Duplicate HMAC key id: {}", id);
+            }
+            parsed.push(HmacKey {
+                id: id.to_string(),
+                secret: secret.as_bytes().to_vec(),
+                active_from,
+            });
+        }
+
+        // Stable: among keys with the same activation, the later entry wins
+        parsed.sort_by_key(|k| k.active_from);
+
+        Ok(Self {
+            keys: parsed,
+            epoch_secs: epoch_secs.filter(|s| *s > 0).map(|s| s as i64),
+        })
+    }
+
+    /// The most recently activated key at `now`
+    fn active_key(&self, now: DateTime<Utc>) -> &HmacKey {
+        self.keys
+            .iter()
+            .rev()
+            .find(|k| k.active_from.is_none_or(|at| at <= now))
+            .unwrap_or(&self.keys[0])
+    }
+
+    /// HMAC a domain with the key active at `now`; returns (key_id, hex HMAC)
+    pub fn hmac_domain(&self, domain: &str, now: DateTime<Utc>) -> (String, String) {
+        let key = self.active_key(now);
+        let (key_id, secret) = match self.epoch_secs {
+            Some(secs) => {
+                let epoch = now.timestamp().div_euclid(secs);
+                (format!("{}.{}", key.id, epoch), hmac(&key.secret, format!("epoch:{}", epoch).as_bytes()))
+            }
+            None => (key.id.clone(), key.secret.clone()),
+        };
+        (key_id, hex::encode(hmac(&secret, domain.as_bytes())))
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_scheduled_rotation() {
+        let ring = HmacKeyring::new("old", Some("k1@2026-03-01T00:00:00Z=new, k2@2027-01-01T00:00:00Z=newer"), None)
+            .unwrap();
+        let (before, h_before) = ring.hmac_domain("example.com", at("2026-02-01T00:00:00Z"));
+        let (after, h_after) = ring.hmac_domain("example.com", at("2026-06-01T00:00:00Z"));
+        assert_eq!((before.as_str(), after.as_str()), ("0", "k1"));
+        assert_ne!(h_before, h_after);
+        assert_eq!(ring.hmac_domain("example.com", at("2027-06-01T00:00:00Z")).0, "k2");
+
+        // Unscheduled keys are active immediately
+        let ring = HmacKeyring::new("old", Some("k1=new"), None).unwrap();
+        assert_eq!(ring.hmac_domain("example.com", Utc::now()).0, "k1");
+    }
+
+    #[test]
+    fn test_epoch_keys_unlinkable_across_epochs() {
+        let ring = HmacKeyring::new("secret", None, Some(86_400)).unwrap();
+        let (id1, a) = ring.hmac_domain("example.com", at("2026-05-01T01:00:00Z"));
+        let (id2, b) = ring.hmac_domain("example.com", at("2026-05-01T23:00:00Z"));
+        let (id3, c) = ring.hmac_domain("example.com", at("2026-05-02T01:00:00Z"));
+        assert_eq!(id1, id2);
+        assert_eq!(a, b);
+        assert_ne!(id1, id3);
+        assert_ne!(a, c);
+        assert!(id1.starts_with("0."));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(HmacKeyring::new("s", Some("nosecret"), None).is_err());
+        assert!(HmacKeyring::new("s", Some("0=dup"), None).is_err());
+        assert!(HmacKeyring::new("s", Some("a.b=x"), None).is_err());
+        assert!(HmacKeyring::new("s", Some("k@tomorrow=x"), None).is_err());
+        assert!(HmacKeyring::new("s", Some("k="), None).is_err());
+    }
+}
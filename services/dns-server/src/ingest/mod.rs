@@ -1,243 +1,745 @@
-//! Tinybird event ingestion
+//! DNS event ingestion
+//!
+//! `EventQueue` applies the privacy policy, collapses repeated lookups,
+//! batches events (or aggregates them in aggregate mode) and hands them to
+//! one `EventSink`, picked with EVENT_SINK: Tinybird, Postgres, rotating
+//! NDJSON files or ClickHouse.
 
+pub mod aggregate;
+pub mod clickhouse;
+pub mod dedup;
+pub mod file;
+pub mod hmac_keys;
+pub mod postgres;
+pub mod privacy;
+pub mod spool;
+pub mod tinybird;
+
+use crate::categorize::CategoryWeight;
+use crate::config::Config;
+use aggregate::{CategoryHistogram, InterestAggregator};
+use axum::async_trait;
 use chrono::{DateTime, Utc};
-use hmac::{Hmac, Mac};
-use serde::Serialize;
-use sha2::Sha256;
+use dedup::Deduplicator;
+use privacy::PrivacyPolicy;
+use serde::{Deserialize, Serialize};
+use spool::Spool;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, mpsc, Notify};
 use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
-/// Max events in queue before dropping oldest
+/// Max events in queue before dropping new ones
 const MAX_QUEUE_SIZE: usize = 10_000;
 /// Retry attempts before giving up on a batch
 const MAX_RETRIES: u32 = 3;
 /// Base delay between retries (doubles each attempt)
 const RETRY_BASE_DELAY_MS: u64 = 500;
+/// Spooled batches replayed per tick
+const REPLAY_BATCHES_PER_TICK: usize = 10;
+/// Per-request limit for HTTP sinks, so a hung backend fails the batch instead of stalling the sender
+const SINK_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the gateway ships to the sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IngestMode {
+    /// One `dns_events` row per query
+    Events,
+    /// Periodic per-wallet category histograms only (`interest_histograms`)
+    Aggregate,
+}
+
+/// Where events are delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SinkKind {
+    Tinybird,
+    /// Partitioned `dns_events` table in the gateway's own database
+    Postgres,
+    /// Rotating NDJSON files (local development, no credentials needed)
+    File,
+    /// ClickHouse HTTP interface
+    Clickhouse,
+}
+
+/// A destination for events and aggregate histograms
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Short name for logs
+    fn name(&self) -> &'static str;
+
+    /// Deliver a batch of raw events
+    async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()>;
+
+    /// Deliver one aggregation window (aggregate mode)
+    async fn send_histograms(&self, rows: &[CategoryHistogram]) -> anyhow::Result<()>;
+}
+
+/// Build the sink selected by EVENT_SINK
+pub fn build_sink(config: &Config, db: &sqlx::PgPool) -> anyhow::Result<Box<dyn EventSink>> {
+    Ok(match config.event_sink {
+        SinkKind::Tinybird => {
+            let token = config
+                .tinybird_token
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("TINYBIRD_TOKEN is required when EVENT_SINK=tinybird"))?;
+            Box::new(tinybird::TinybirdSink::new(token, &config.tinybird_endpoint))
+        }
+        SinkKind::Postgres => Box::new(postgres::PostgresSink::new(db.clone())),
+        SinkKind::File => Box::new(file::FileSink::new(
+            &config.event_file_dir,
+            config.event_file_max_bytes,
+            config.event_file_keep,
+        )),
+        SinkKind::Clickhouse => {
+            let url = config
+                .clickhouse_url
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("CLICKHOUSE_URL is required when EVENT_SINK=clickhouse"))?;
+            Box::new(clickhouse::ClickHouseSink::new(
+                url,
+                &config.clickhouse_database,
+                config.clickhouse_user.clone(),
+                config.clickhouse_password.clone(),
+            ))
+        }
+    })
+}
+
+/// HTTP client for sinks that post batches over HTTP
+pub(crate) fn sink_http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(SINK_HTTP_TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client")
+}
 
-/// DNS event to send to Tinybird
-#[derive(Debug, Clone, Serialize)]
+/// Serialize rows as newline-delimited JSON (no trailing newline)
+pub(crate) fn ndjson<T: Serialize>(rows: &[T]) -> anyhow::Result<String> {
+    Ok(rows
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?
+        .join("\n"))
+}
+
+/// DNS event as delivered to sinks
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsEvent {
     pub ts: DateTime<Utc>,
     pub wallet_id: String,
     pub device_id: Uuid,
     pub etld1: String,
     pub domain_hmac: String,
+    /// HMAC key `domain_hmac` was computed with (see `hmac_keys`)
+    #[serde(default)]
+    pub key_id: String,
     pub qtype: String,
     pub action: String,
-    pub category_id: Option<i32>,
+    /// Weighted categories of the queried name, heaviest first (empty if uncategorized)
+    pub categories: Vec<CategoryWeight>,
     pub latency_ms: u32,
+    /// Identical lookups collapsed into this event by the dedup window
+    #[serde(default = "one")]
+    pub count: u32,
+    /// Browsing session (visit) this lookup belongs to (set by dedup)
+    #[serde(default)]
+    pub session_id: Option<Uuid>,
+    /// First lookup of its session, or any lookup that skipped dedup:
+    /// scoring counts these as visits
+    #[serde(default)]
+    pub session_start: bool,
+    /// CDN, analytics or telemetry host (categories are left empty)
+    #[serde(default)]
+    pub infra: bool,
 }
 
-/// Tinybird client with batched event sending
-pub struct TinybirdClient {
-    token: String,
-    endpoint: String,
-    client: reqwest::Client,
-    queue: Arc<Mutex<Vec<DnsEvent>>>,
+fn one() -> u32 {
+    1
 }
 
-impl TinybirdClient {
-    pub fn new(token: &str, endpoint: &str) -> Self {
+/// Delivery counters (batches for flushes, events otherwise)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct IngestStats {
+    /// Batches accepted by the sink
+    pub flushes: u64,
+    /// Batches that failed after all retries
+    pub flush_failures: u64,
+    /// Left on disk after failed delivery
+    pub spooled: u64,
+    /// Delivered from the spool
+    pub replayed: u64,
+    /// Discarded: queue overflow, spool eviction or unreadable spool files
+    pub dropped: u64,
+}
+
+/// Batched event queue in front of the configured sink
+///
+/// The hot path (`queue_event`) is a non-blocking send on a bounded
+/// channel; only the batch sender drains it.
+pub struct EventQueue {
+    sink: Box<dyn EventSink>,
+    tx: mpsc::Sender<DnsEvent>,
+    rx: std::sync::Mutex<mpsc::Receiver<DnsEvent>>,
+    /// Events whose delivery failed (no spool), sent before the channel
+    retry: std::sync::Mutex<Vec<DnsEvent>>,
+    /// Events per sink request
+    batch_size: usize,
+    /// Signalled when a full batch is waiting
+    batch_ready: Notify,
+    /// Applied to every event before it is queued
+    privacy: PrivacyPolicy,
+    /// Set in aggregate mode: events feed counters instead of the queue
+    aggregator: Option<InterestAggregator>,
+    /// Write-ahead spool; without it failed batches are requeued in memory
+    spool: Option<Spool>,
+    /// Collapses repeated lookups before they are queued or aggregated
+    dedup: Option<Deduplicator>,
+    flushes: AtomicU64,
+    flush_failures: AtomicU64,
+    spooled: AtomicU64,
+    replayed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    pub fn new(sink: Box<dyn EventSink>, privacy: PrivacyPolicy, batch_size: usize) -> Self {
+        let (tx, rx) = mpsc::channel(MAX_QUEUE_SIZE);
         Self {
-            token: token.to_string(),
-            endpoint: endpoint.to_string(),
-            client: reqwest::Client::new(),
-            queue: Arc::new(Mutex::new(Vec::with_capacity(1000))),
+            sink,
+            tx,
+            rx: std::sync::Mutex::new(rx),
+            retry: std::sync::Mutex::new(Vec::new()),
+            batch_size: batch_size.clamp(1, MAX_QUEUE_SIZE),
+            batch_ready: Notify::new(),
+            privacy,
+            aggregator: None,
+            spool: None,
+            dedup: None,
+            flushes: AtomicU64::new(0),
+            flush_failures: AtomicU64::new(0),
+            spooled: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
         }
     }
 
-    /// Queue an event for batched sending (drops oldest if queue full)
-    pub async fn queue_event(&self, event: DnsEvent) {
-        let mut queue = self.queue.lock().await;
-        if queue.len() >= MAX_QUEUE_SIZE {
-            let dropped = queue.len() - MAX_QUEUE_SIZE + 1;
-            queue.drain(0..dropped);
-            tracing::warn!("Tinybird queue overflow, dropped {} oldest events", dropped);
-        }
-        queue.push(event);
+    /// Persist batches to `spool` before sending and replay failed ones
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
     }
 
-    /// Flush queued events to Tinybird with retry
-    pub async fn flush(&self) -> anyhow::Result<usize> {
-        let events = {
-            let mut queue = self.queue.lock().await;
-            if queue.is_empty() {
-                return Ok(0);
-            }
-            std::mem::take(&mut *queue)
-        };
+    /// Hold events in a dedup window and group them into sessions
+    pub fn with_dedup(mut self, dedup: Deduplicator) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
 
-        let original_count = events.len();
+    /// Switch to aggregate mode: only periodic category histograms are sent
+    pub fn with_aggregator(mut self, aggregator: InterestAggregator) -> Self {
+        self.aggregator = Some(aggregator);
+        self
+    }
 
-        // Convert to NDJSON, keeping track of successfully serialized events for potential requeue
-        let mut serialization_errors = 0usize;
-        let mut serializable_events: Vec<DnsEvent> = Vec::with_capacity(events.len());
-        let mut serialized: Vec<String> = Vec::with_capacity(events.len());
+    /// Name of the configured sink
+    pub fn sink_name(&self) -> &'static str {
+        self.sink.name()
+    }
 
-        for event in events {
-            match serde_json::to_string(&event) {
-                Ok(s) => {
-                    serialized.push(s);
-                    serializable_events.push(event);
+    /// Queue an event for batched sending. Never blocks: when the queue is
+    /// full the event is dropped and counted. Wakes the batch sender once a
+    /// full batch is waiting. Sensitive-category events are redacted or
+    /// dropped per the privacy policy, and repeats are held for dedup.
+    pub fn queue_event(&self, event: DnsEvent) {
+        let Some(event) = self.privacy.apply(event) else {
+            return;
+        };
+        let event = match &self.dedup {
+            Some(dedup) => match dedup.offer(event) {
+                Some(event) => event,
+                None => return,
+            },
+            None => event,
+        };
+        // Not sessionized, so each lookup counts as its own visit
+        self.enqueue(DnsEvent {
+            session_start: true,
+            ..event
+        });
+    }
+
+    /// Pass events whose dedup window closed (all of them if `all`) on to
+    /// the queue or aggregator
+    pub fn release_dedup(&self, all: bool) {
+        if let Some(dedup) = &self.dedup {
+            for event in dedup.release(Utc::now(), all) {
+                self.enqueue(event);
+            }
+        }
+    }
+
+    fn enqueue(&self, event: DnsEvent) {
+        if let Some(aggregator) = &self.aggregator {
+            aggregator.record(&event);
+            return;
+        }
+        match self.tx.try_send(event) {
+            Ok(()) => {
+                if self.channel_len() >= self.batch_size {
+                    self.batch_ready.notify_one();
                 }
-                Err(_) => {
-                    serialization_errors += 1;
+            }
+            Err(_) => {
+                // Log once per batch worth of drops, not per query
+                if self.dropped.fetch_add(1, Ordering::Relaxed) % (self.batch_size as u64) == 0 {
+                    tracing::warn!("Event queue full, dropping new events");
                 }
             }
         }
+    }
 
-        let sent_count = serialized.len();
+    /// Resolves once `queue_event` has seen a full batch waiting
+    pub async fn batch_ready(&self) {
+        self.batch_ready.notified().await
+    }
 
-        if serialization_errors > 0 {
-            tracing::warn!(
-                "Dropped {} of {} events due to serialization errors",
-                serialization_errors,
-                original_count
-            );
+    fn channel_len(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Take up to `batch_size` events, requeued ones first
+    fn take_chunk(&self) -> Vec<DnsEvent> {
+        let mut chunk = {
+            let mut retry = self.retry.lock().unwrap_or_else(|e| e.into_inner());
+            let n = retry.len().min(self.batch_size);
+            retry.drain(..n).collect::<Vec<_>>()
+        };
+        let mut rx = self.rx.lock().unwrap_or_else(|e| e.into_inner());
+        while chunk.len() < self.batch_size {
+            match rx.try_recv() {
+                Ok(event) => chunk.push(event),
+                Err(_) => break,
+            }
         }
+        chunk
+    }
 
-        // Short-circuit if all events failed serialization
-        if sent_count == 0 {
-            return Ok(0);
+    /// Flush queued events to the sink in `batch_size` chunks, stopping at
+    /// the first chunk that fails after retries. With a spool, everything
+    /// still queued is then spooled so an outage fills the disk, not memory.
+    pub async fn flush(&self) -> anyhow::Result<usize> {
+        let mut sent = 0;
+        loop {
+            let chunk = self.take_chunk();
+            if chunk.is_empty() {
+                return Ok(sent);
+            }
+            let len = chunk.len();
+            if let Err(e) = self.deliver(chunk).await {
+                self.spool_pending().await;
+                return Err(e);
+            }
+            sent += len;
+        }
+    }
+
+    /// Move every queued event to the spool in `batch_size` chunks without
+    /// trying the sink; replay delivers them once it recovers
+    async fn spool_pending(&self) {
+        let Some(spool) = &self.spool else {
+            return;
+        };
+        let mut total = 0;
+        loop {
+            let chunk = self.take_chunk();
+            if chunk.is_empty() {
+                break;
+            }
+            match spool.write(&chunk).await {
+                Ok((_, evicted)) => {
+                    if evicted > 0 {
+                        self.dropped.fetch_add(evicted, Ordering::Relaxed);
+                        tracing::warn!("Event spool full, evicted {} oldest spooled events", evicted);
+                    }
+                    self.spooled.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                    total += chunk.len();
+                }
+                Err(e) => {
+                    tracing::error!("Failed to spool event batch: {}", e);
+                    self.requeue(chunk);
+                    break;
+                }
+            }
+        }
+        if total > 0 {
+            tracing::warn!("Spooled {} queued events during {} outage", total, self.sink.name());
         }
+    }
 
-        let ndjson = serialized.join("\n");
+    /// Send one chunk with retry: spooled first if a spool is configured,
+    /// otherwise requeued in memory on failure
+    async fn deliver(&self, events: Vec<DnsEvent>) -> anyhow::Result<()> {
+        let sent_count = events.len();
+
+        // Write ahead so the batch survives a crash or a failed send
+        let spooled = match &self.spool {
+            Some(spool) => match spool.write(&events).await {
+                Ok((entry, evicted)) => {
+                    if evicted > 0 {
+                        self.dropped.fetch_add(evicted, Ordering::Relaxed);
+                        tracing::warn!("Event spool full, evicted {} oldest spooled events", evicted);
+                    }
+                    Some(entry)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to spool event batch: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
 
         // Try to send with retries
-        let url = format!("{}/v0/events?name=dns_events", self.endpoint);
         let mut last_error = None;
-
         for attempt in 0..MAX_RETRIES {
-            match self.send_batch(&url, &ndjson).await {
+            match self.sink.send_events(&events).await {
                 Ok(()) => {
-                    tracing::debug!("Flushed {} events to Tinybird", sent_count);
-                    return Ok(sent_count);
+                    if let (Some(spool), Some(entry)) = (&self.spool, &spooled) {
+                        if let Err(e) = spool.remove(&entry.path).await {
+                            tracing::error!("Failed to remove delivered spool batch: {}", e);
+                        }
+                    }
+                    self.flushes.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Flushed {} events to {}", sent_count, self.sink.name());
+                    return Ok(());
                 }
                 Err(e) => {
                     last_error = Some(e);
-                    if attempt + 1 < MAX_RETRIES {
-                        let delay = Duration::from_millis(RETRY_BASE_DELAY_MS * (1 << attempt));
-                        tracing::warn!(
-                            "Tinybird send failed (attempt {}/{}), retrying in {:?}",
-                            attempt + 1,
-                            MAX_RETRIES,
-                            delay
-                        );
-                        sleep(delay).await;
-                    }
+                    self.backoff(attempt).await;
                 }
             }
         }
 
-        // All retries failed - requeue only serializable events (respecting max size)
-        {
-            let mut queue = self.queue.lock().await;
-            let space_available = MAX_QUEUE_SIZE.saturating_sub(queue.len());
-            let to_requeue = serializable_events.len().min(space_available);
-
-            if to_requeue > 0 {
-                // Prepend failed events to front of queue
-                let requeue_events = serializable_events.into_iter().take(to_requeue);
-                let old_events = std::mem::take(&mut *queue);
-                queue.extend(requeue_events);
-                queue.extend(old_events);
-                tracing::warn!(
-                    "Requeued {} events after {} failed attempts ({} dropped due to overflow, {} dropped due to serialization)",
-                    to_requeue,
-                    MAX_RETRIES,
-                    sent_count.saturating_sub(to_requeue),
-                    serialization_errors
-                );
-            } else {
-                tracing::error!(
-                    "Dropped {} events after {} failed attempts (queue full, {} serialization errors)",
-                    sent_count,
-                    MAX_RETRIES,
-                    serialization_errors
-                );
-            }
+        self.flush_failures.fetch_add(1, Ordering::Relaxed);
+        if spooled.is_some() {
+            self.spooled.fetch_add(sent_count as u64, Ordering::Relaxed);
+            tracing::warn!(
+                "Spooled {} events after {} failed attempts, will replay",
+                sent_count,
+                MAX_RETRIES
+            );
+            return Err(last_error.unwrap_or_else(|| anyhow::anyhow!("{} flush failed", self.sink.name())));
         }
 
-        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Tinybird flush failed")))
+        // All retries failed - requeue
+        self.requeue(events);
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("{} flush failed", self.sink.name())))
     }
 
-    async fn send_batch(&self, url: &str, ndjson: &str) -> anyhow::Result<()> {
-        let resp = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.token))
-            .header("Content-Type", "application/x-ndjson")
-            .body(ndjson.to_string())
-            .send()
-            .await?;
+    /// Put undelivered events back in front of the queue (respecting max size)
+    fn requeue(&self, events: Vec<DnsEvent>) {
+        let count = events.len();
+        let mut retry = self.retry.lock().unwrap_or_else(|e| e.into_inner());
+        let space_available = MAX_QUEUE_SIZE.saturating_sub(retry.len());
+        let to_requeue = count.min(space_available);
 
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("{} - {}", status, body.trim());
+        self.dropped
+            .fetch_add((count - to_requeue) as u64, Ordering::Relaxed);
+
+        if to_requeue > 0 {
+            // Prepend failed events so they go out first next time
+            let old_events = std::mem::take(&mut *retry);
+            retry.extend(events.into_iter().take(to_requeue));
+            retry.extend(old_events);
+            tracing::warn!(
+                "Requeued {} events ({} dropped due to overflow)",
+                to_requeue,
+                count - to_requeue
+            );
+        } else {
+            tracing::error!("Dropped {} undelivered events (queue full)", count);
         }
+    }
+
+    /// Send spooled batches oldest-first, stopping at the first failure.
+    /// Returns the number of events delivered.
+    pub async fn replay_spool(&self) -> anyhow::Result<usize> {
+        let Some(spool) = &self.spool else {
+            return Ok(0);
+        };
 
-        Ok(())
+        let mut replayed = 0;
+        for entry in spool.entries().await?.into_iter().take(REPLAY_BATCHES_PER_TICK) {
+            let events = match spool.read(&entry.path).await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::error!(path = %entry.path.display(), "Discarding unreadable spool batch: {}", e);
+                    spool.remove(&entry.path).await?;
+                    continue;
+                }
+            };
+            self.sink.send_events(&events).await?;
+            spool.remove(&entry.path).await?;
+            self.replayed.fetch_add(events.len() as u64, Ordering::Relaxed);
+            replayed += events.len();
+        }
+        Ok(replayed)
     }
 
-    /// Compute HMAC of domain for privacy-preserving storage
-    pub fn hmac_domain(&self, domain: &str, secret: &str) -> String {
-        type HmacSha256 = Hmac<Sha256>;
-        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
-            .expect("HMAC can take key of any size");
-        mac.update(domain.as_bytes());
-        let result = mac.finalize();
-        hex::encode(result.into_bytes())
+    /// Delivery counters since startup
+    pub fn stats(&self) -> IngestStats {
+        IngestStats {
+            flushes: self.flushes.load(Ordering::Relaxed),
+            flush_failures: self.flush_failures.load(Ordering::Relaxed),
+            spooled: self.spooled.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
     }
 
-    /// Get queue length
-    pub async fn queue_len(&self) -> usize {
-        self.queue.lock().await.len()
+    /// Close the aggregation window and send its histogram (aggregate mode only)
+    pub async fn flush_aggregates(&self) -> anyhow::Result<usize> {
+        let Some(aggregator) = &self.aggregator else {
+            return Ok(0);
+        };
+        let rows = aggregator.drain();
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut last_error = None;
+        for attempt in 0..MAX_RETRIES {
+            match self.sink.send_histograms(&rows).await {
+                Ok(()) => return Ok(rows.len()),
+                Err(e) => {
+                    last_error = Some(e);
+                    self.backoff(attempt).await;
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("{} flush failed", self.sink.name())))
+    }
+
+    /// Sleep before the next attempt (no-op after the last one)
+    async fn backoff(&self, attempt: u32) {
+        if attempt + 1 < MAX_RETRIES {
+            let delay = Duration::from_millis(RETRY_BASE_DELAY_MS * (1 << attempt));
+            tracing::warn!(
+                "{} send failed (attempt {}/{}), retrying in {:?}",
+                self.sink.name(),
+                attempt + 1,
+                MAX_RETRIES,
+                delay
+            );
+            sleep(delay).await;
+        }
+    }
+
+    /// Events waiting to be sent (queued plus requeued after failures)
+    pub fn queue_len(&self) -> usize {
+        self.channel_len() + self.retry.lock().unwrap_or_else(|e| e.into_inner()).len()
     }
 }
 
-/// Background task that periodically flushes events to Tinybird
+/// Background task that periodically flushes events to the sink
 pub async fn batch_sender(state: Arc<crate::AppState>, mut shutdown: broadcast::Receiver<()>) {
-    let interval = tokio::time::Duration::from_secs(state.config.tinybird_flush_interval);
-    let batch_size = state.config.tinybird_batch_size;
+    let interval = tokio::time::Duration::from_secs(state.config.event_flush_interval());
 
     let mut ticker = tokio::time::interval(interval);
 
+    // Aggregation windows (no-op outside aggregate mode); skip the immediate first tick
+    let aggregate_interval = Duration::from_secs(state.config.aggregate_interval_secs.max(60));
+    let mut aggregate_ticker =
+        tokio::time::interval_at(tokio::time::Instant::now() + aggregate_interval, aggregate_interval);
+
+    // Deliver whatever a previous run left in the spool
+    replay_spool(&state).await;
+
     loop {
         tokio::select! {
             _ = shutdown.recv() => {
                 // Final flush on shutdown
-                if let Err(e) = state.tinybird.flush().await {
-                    tracing::error!("Final Tinybird flush failed: {}", e);
+                state.events.release_dedup(true);
+                if let Err(e) = state.events.flush().await {
+                    tracing::error!("Final {} flush failed: {}", state.events.sink_name(), e);
                 }
-                tracing::info!("Tinybird batch sender stopped");
+                if let Err(e) = state.events.flush_aggregates().await {
+                    tracing::error!("Final interest histogram flush failed: {}", e);
+                }
+                tracing::info!("Event batch sender stopped");
                 break;
             }
+            _ = aggregate_ticker.tick() => {
+                match state.events.flush_aggregates().await {
+                    Ok(n) if n > 0 => {
+                        tracing::info!("Sent {} interest histogram cells to {}", n, state.events.sink_name())
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::error!("Interest histogram flush failed: {}", e),
+                }
+            }
+            _ = state.events.batch_ready() => {
+                // A full batch is waiting; don't let a burst sit until the next tick
+                match state.events.flush().await {
+                    Ok(n) => tracing::debug!("Flushed {} events to {} (batch full)", n, state.events.sink_name()),
+                    Err(e) => tracing::error!("{} batch flush failed: {}", state.events.sink_name(), e),
+                }
+            }
             _ = ticker.tick() => {
-                // Check if we have enough events or interval elapsed
-                let queue_len = state.tinybird.queue_len().await;
-                if queue_len > 0 {
-                    match state.tinybird.flush().await {
+                state.events.release_dedup(false);
+                let mut delivered = true;
+                if state.events.queue_len() > 0 {
+                    match state.events.flush().await {
                         Ok(n) => {
                             if n > 0 {
-                                tracing::info!("Flushed {} events to Tinybird", n);
+                                tracing::info!("Flushed {} events to {}", n, state.events.sink_name());
                             }
                         }
                         Err(e) => {
-                            tracing::error!("Tinybird flush failed: {}", e);
+                            delivered = false;
+                            tracing::error!("{} flush failed: {}", state.events.sink_name(), e);
                         }
                     }
                 }
+                // No failure this tick: catch up on spooled batches
+                if delivered {
+                    replay_spool(&state).await;
+                }
             }
         }
+    }
+}
 
-        // Also flush if queue exceeds batch size
-        if state.tinybird.queue_len().await >= batch_size {
-            if let Err(e) = state.tinybird.flush().await {
-                tracing::error!("Tinybird batch flush failed: {}", e);
-            }
+/// Replay spooled batches, logging the outcome
+async fn replay_spool(state: &crate::AppState) {
+    match state.events.replay_spool().await {
+        Ok(n) if n > 0 => tracing::info!("Replayed {} spooled events to {}", n, state.events.sink_name()),
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Spool replay failed, will retry: {}", e),
+    }
+}
+
+/// Baseline event for tests; override fields with `..test_event()`
+#[cfg(test)]
+pub(crate) fn test_event() -> DnsEvent {
+    DnsEvent {
+        ts: Utc::now(),
+        wallet_id: "0xabc".to_string(),
+        device_id: Uuid::nil(),
+        etld1: "example.com".to_string(),
+        domain_hmac: "deadbeef".to_string(),
+        key_id: String::new(),
+        qtype: "A".to_string(),
+        action: "allow".to_string(),
+        categories: vec![],
+        latency_ms: 1,
+        count: 1,
+        session_id: None,
+        session_start: false,
+        infra: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the size of every batch it receives
+    #[derive(Default)]
+    struct RecordingSink {
+        batches: std::sync::Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl EventSink for Arc<RecordingSink> {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+            self.batches.lock().unwrap().push(events.len());
+            Ok(())
+        }
+
+        async fn send_histograms(&self, _rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Rejects every batch
+    struct DownSink;
+
+    #[async_trait]
+    impl EventSink for DownSink {
+        fn name(&self) -> &'static str {
+            "down"
+        }
+
+        async fn send_events(&self, _events: &[DnsEvent]) -> anyhow::Result<()> {
+            anyhow::bail!("sink down")
+        }
+
+        async fn send_histograms(&self, _rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+            anyhow::bail!("sink down")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outage_spools_whole_queue() {
+        let dir = std::env::temp_dir().join(format!("hp-dns-gw-outage-{}", Uuid::new_v4()));
+        let spool = Spool::open(dir.to_str().unwrap(), 1 << 20).await.unwrap();
+        let queue = EventQueue::new(Box::new(DownSink), PrivacyPolicy::default(), 4).with_spool(spool);
+
+        for _ in 0..10 {
+            queue.queue_event(test_event());
         }
+        // What a failed flush does after its chunk exhausts the retries
+        queue.spool_pending().await;
+        assert_eq!(queue.queue_len(), 0);
+        assert_eq!(queue.stats().spooled, 10);
+        assert_eq!(queue.spool.as_ref().unwrap().entries().await.unwrap().len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch_signal_and_chunked_flush() {
+        let sink = Arc::new(RecordingSink::default());
+        let queue = EventQueue::new(Box::new(sink.clone()), PrivacyPolicy::default(), 4);
+
+        for _ in 0..10 {
+            queue.queue_event(test_event());
+        }
+        assert_eq!(queue.queue_len(), 10);
+        // The full batch was signalled without waiting for a tick
+        tokio::time::timeout(Duration::from_millis(100), queue.batch_ready())
+            .await
+            .expect("batch_ready should fire");
+
+        assert_eq!(queue.flush().await.unwrap(), 10);
+        assert_eq!(*sink.batches.lock().unwrap(), vec![4, 4, 2]);
+        assert_eq!(queue.queue_len(), 0);
+    }
+
+    #[test]
+    fn test_events_bypassing_dedup_count_as_visits() {
+        let new_queue = || EventQueue::new(Box::new(Arc::new(RecordingSink::default())), PrivacyPolicy::default(), 4);
+        let queue = new_queue();
+        queue.queue_event(test_event());
+        assert!(queue.take_chunk()[0].session_start);
+
+        let queue = new_queue().with_dedup(Deduplicator::new(10, 1800));
+        queue.queue_event(test_event());
+        queue.queue_event(DnsEvent {
+            etld1: String::new(),
+            ..test_event()
+        });
+        // Only the event without an eTLD+1 skipped the window
+        let chunk = queue.take_chunk();
+        assert_eq!(chunk.len(), 1);
+        assert!(chunk[0].etld1.is_empty() && chunk[0].session_start);
     }
 }
@@ -0,0 +1,148 @@
+//! Postgres sink: the gateway's own database (migration 011)
+//!
+//! `dns_events` is range-partitioned by month on `ts`; partitions are
+//! created on first write to a month, so old months can be dropped with a
+//! plain `DROP TABLE dns_events_yYYYYmMM`.
+
+use super::aggregate::CategoryHistogram;
+use super::{DnsEvent, EventSink};
+use axum::async_trait;
+use chrono::{Datelike, NaiveDate};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Rows per INSERT (keeps bind parameters well under Postgres' 65535 limit)
+const INSERT_CHUNK: usize = 1000;
+
+pub struct PostgresSink {
+    db: PgPool,
+    /// Partitions known to exist, by table name
+    partitions: Mutex<HashSet<String>>,
+}
+
+impl PostgresSink {
+    pub fn new(db: PgPool) -> Self {
+        Self {
+            db,
+            partitions: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Create the monthly partitions covering `events` if missing
+    async fn ensure_partitions(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+        let months: HashSet<(i32, u32)> = events.iter().map(|e| (e.ts.year(), e.ts.month())).collect();
+        for (year, month) in months {
+            let name = partition_name(year, month);
+            if self.partitions.lock().unwrap_or_else(|e| e.into_inner()).contains(&name) {
+                continue;
+            }
+            sqlx::query(&partition_ddl(&name, year, month))
+                .execute(&self.db)
+                .await?;
+            self.partitions.lock().unwrap_or_else(|e| e.into_inner()).insert(name);
+        }
+        Ok(())
+    }
+}
+
+fn partition_name(year: i32, month: u32) -> String {
+    format!("dns_events_y{:04}m{:02}", year, month)
+}
+
+/// Create one monthly partition. Bounds are explicit UTC midnights, since
+/// events are bucketed by UTC month and bare dates would be read in the
+/// session's TimeZone.
+fn partition_ddl(name: &str, year: i32, month: u32) -> String {
+    let (from, to) = month_bounds(year, month);
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} PARTITION OF dns_events \
+         FOR VALUES FROM ('{} 00:00:00+00') TO ('{} 00:00:00+00')",
+        name, from, to
+    )
+}
+
+/// First day of the month and of the following month
+fn month_bounds(year: i32, month: u32) -> (NaiveDate, NaiveDate) {
+    let from = NaiveDate::from_ymd_opt(year, month, 1).expect("valid month");
+    let to = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    }
+    .expect("valid month");
+    (from, to)
+}
+
+#[async_trait]
+impl EventSink for PostgresSink {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+        self.ensure_partitions(events).await?;
+
+        let mut tx = self.db.begin().await?;
+        for chunk in events.chunks(INSERT_CHUNK) {
+            let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+                "INSERT INTO dns_events (ts, wallet_id, device_id, etld1, domain_hmac, key_id, qtype, action, \
+                 category_ids, category_weights, latency_ms, count, session_id, session_start, infra) ",
+            );
+            qb.push_values(chunk, |mut b, e| {
+                b.push_bind(e.ts)
+                    .push_bind(&e.wallet_id)
+                    .push_bind(e.device_id)
+                    .push_bind(&e.etld1)
+                    .push_bind(&e.domain_hmac)
+                    .push_bind(&e.key_id)
+                    .push_bind(&e.qtype)
+                    .push_bind(&e.action)
+                    .push_bind(e.categories.iter().map(|c| c.category_id).collect::<Vec<i32>>())
+                    .push_bind(e.categories.iter().map(|c| c.weight).collect::<Vec<f32>>())
+                    .push_bind(e.latency_ms as i32)
+                    .push_bind(e.count as i32)
+                    .push_bind(e.session_id)
+                    .push_bind(e.session_start)
+                    .push_bind(e.infra);
+            });
+            qb.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn send_histograms(&self, rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        for chunk in rows.chunks(INSERT_CHUNK) {
+            let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(
+                "INSERT INTO interest_histograms (window_start, window_end, wallet_id, category_id, weight, epsilon) ",
+            );
+            qb.push_values(chunk, |mut b, r| {
+                b.push_bind(r.window_start)
+                    .push_bind(r.window_end)
+                    .push_bind(&r.wallet_id)
+                    .push_bind(r.category_id)
+                    .push_bind(r.weight)
+                    .push_bind(r.epsilon);
+            });
+            qb.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_month_partitions() {
+        assert_eq!(partition_name(2026, 3), "dns_events_y2026m03");
+        let (from, to) = month_bounds(2026, 12);
+        assert_eq!((from.to_string(), to.to_string()), ("2026-12-01".into(), "2027-01-01".into()));
+        assert!(partition_ddl("dns_events_y2026m11", 2026, 11)
+            .ends_with("FROM ('2026-11-01 00:00:00+00') TO ('2026-12-01 00:00:00+00')"));
+    }
+}
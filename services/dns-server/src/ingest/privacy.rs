@@ -0,0 +1,138 @@
+//! Server-side privacy policy for sensitive categories
+//!
+//! Health, Adult, Religion and Politics are excluded from matching, so there
+//! is no reason to ship who visited them. Before an event is queued, each of
+//! its categories is looked up here: `redact` strips the wallet, device,
+//! eTLD+1 and domain HMAC (keeping category, action and timing for aggregate
+//! stats), `drop` discards the event entirely. The strictest action among an
+//! event's categories wins.
+
+use super::DnsEvent;
+use crate::categorize::Category;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Wallet placeholder on redacted events
+pub const REDACTED: &str = "redacted";
+
+/// What happens to events touching a category (ordered by strictness)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PrivacyAction {
+    Keep,
+    Redact,
+    Drop,
+}
+
+impl PrivacyAction {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "keep" => Some(PrivacyAction::Keep),
+            "redact" => Some(PrivacyAction::Redact),
+            "drop" => Some(PrivacyAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// Per-category privacy actions (unlisted categories are kept)
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyPolicy {
+    actions: HashMap<i32, PrivacyAction>,
+}
+
+impl PrivacyPolicy {
+    /// Parse SENSITIVE_CATEGORY_POLICY: comma-separated `slug=keep|redact|drop`
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut actions = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (slug, action) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid privacy policy entry (want slug=action): {}", entry))?;
+            let category = Category::from_slug(slug)
+                .ok_or_else(|| anyhow::anyhow!("Unknown category in privacy policy: {}", slug))?;
+            let action = PrivacyAction::parse(action.trim())
+                .ok_or_else(|| anyhow::anyhow!("Unknown privacy action (want keep, redact or drop): {}", action))?;
+            actions.insert(category as i32, action);
+        }
+        Ok(Self { actions })
+    }
+
+    /// The strictest action among the event's categories
+    pub fn action_for(&self, event: &DnsEvent) -> PrivacyAction {
+        event
+            .categories
+            .iter()
+            .filter_map(|c| self.actions.get(&c.category_id))
+            .copied()
+            .max()
+            .unwrap_or(PrivacyAction::Keep)
+    }
+
+    /// Apply the policy; None means the event must not be queued
+    pub fn apply(&self, mut event: DnsEvent) -> Option<DnsEvent> {
+        match self.action_for(&event) {
+            PrivacyAction::Keep => Some(event),
+            PrivacyAction::Redact => {
+                event.wallet_id = REDACTED.to_string();
+                event.device_id = Uuid::nil();
+                event.etld1 = String::new();
+                event.domain_hmac = String::new();
+                event.key_id = String::new();
+                Some(event)
+            }
+            PrivacyAction::Drop => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::test_event;
+    use crate::categorize::CategoryWeight;
+
+    fn event(categories: &[Category]) -> DnsEvent {
+        DnsEvent {
+            device_id: Uuid::new_v4(),
+            categories: categories
+                .iter()
+                .map(|&c| CategoryWeight { category_id: c as i32, weight: 1.0 / categories.len() as f32 })
+                .collect(),
+            ..test_event()
+        }
+    }
+
+    #[test]
+    fn test_redact_and_drop() {
+        let policy = PrivacyPolicy::parse("health=redact, adult=drop, news=keep").unwrap();
+
+        let redacted = policy.apply(event(&[Category::Health])).unwrap();
+        assert_eq!(redacted.wallet_id, REDACTED);
+        assert!(redacted.device_id.is_nil());
+        assert!(redacted.etld1.is_empty() && redacted.domain_hmac.is_empty());
+        assert_eq!(redacted.categories.len(), 1);
+
+        assert!(policy.apply(event(&[Category::Adult])).is_none());
+
+        let kept = policy.apply(event(&[Category::Music])).unwrap();
+        assert_eq!((kept.wallet_id.as_str(), kept.etld1.as_str()), ("0xabc", "example.com"));
+        assert!(policy.apply(event(&[])).is_some());
+    }
+
+    #[test]
+    fn test_strictest_category_wins() {
+        let policy = PrivacyPolicy::parse("health=redact,adult=drop").unwrap();
+        let mixed = event(&[Category::Music, Category::Health]);
+        assert_eq!(policy.action_for(&mixed), PrivacyAction::Redact);
+        let mixed = event(&[Category::Health, Category::Adult]);
+        assert_eq!(policy.action_for(&mixed), PrivacyAction::Drop);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PrivacyPolicy::parse("").unwrap().actions.is_empty());
+        assert!(PrivacyPolicy::parse("health").is_err());
+        assert!(PrivacyPolicy::parse("knitting=drop").is_err());
+        assert!(PrivacyPolicy::parse("health=hide").is_err());
+    }
+}
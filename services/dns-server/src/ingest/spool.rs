@@ -0,0 +1,162 @@
+//! Write-ahead spool for event batches (EVENT_SPOOL_DIR)
+//!
+//! Each flushed batch is written to `<dir>/<seq>.ndjson` before it is sent
+//! and removed once the sink accepts it. When a batch fails, the rest of
+//! the queue is spooled unsent. Batches whose delivery fails stay on disk
+//! and are replayed oldest-first on later ticks and after a restart, so a
+//! crash or sink outage no longer loses everything in memory. When the
+//! spool would exceed its size cap, the oldest batches are evicted.
+
+use super::{ndjson, DnsEvent};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    next_seq: AtomicU64,
+}
+
+/// A spooled batch file
+#[derive(Debug, Clone)]
+pub struct SpoolEntry {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl Spool {
+    /// Open (creating if needed) the spool directory, discarding partial writes
+    pub async fn open(dir: &str, max_bytes: u64) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(dir);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut next_seq = 0;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "tmp") {
+                tokio::fs::remove_file(&path).await?;
+            } else if let Some(seq) = seq_of(&path) {
+                next_seq = next_seq.max(seq + 1);
+            }
+        }
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            next_seq: AtomicU64::new(next_seq),
+        })
+    }
+
+    /// Spooled batches, oldest first
+    pub async fn entries(&self) -> anyhow::Result<Vec<SpoolEntry>> {
+        let mut found = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if let Some(seq) = seq_of(&path) {
+                found.push((seq, SpoolEntry { path, bytes: entry.metadata().await?.len() }));
+            }
+        }
+        found.sort_by_key(|(seq, _)| *seq);
+        Ok(found.into_iter().map(|(_, e)| e).collect())
+    }
+
+    /// Persist a batch. Returns its entry and the number of events evicted
+    /// from older batches to stay under the size cap.
+    pub async fn write(&self, events: &[DnsEvent]) -> anyhow::Result<(SpoolEntry, u64)> {
+        let body = ndjson(events)?;
+        let bytes = body.len() as u64;
+        if bytes > self.max_bytes {
+            anyhow::bail!("batch of {} bytes exceeds spool cap of {} bytes", bytes, self.max_bytes);
+        }
+
+        let existing = self.entries().await?;
+        let mut total: u64 = existing.iter().map(|e| e.bytes).sum();
+        let mut evicted = 0;
+        for old in &existing {
+            if total + bytes <= self.max_bytes {
+                break;
+            }
+            evicted += self.read(&old.path).await.map(|e| e.len() as u64).unwrap_or(0);
+            self.remove(&old.path).await?;
+            total -= old.bytes;
+        }
+
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{:020}.ndjson", seq));
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, body).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok((SpoolEntry { path, bytes }, evicted))
+    }
+
+    /// Read a spooled batch back
+    pub async fn read(&self, path: &Path) -> anyhow::Result<Vec<DnsEvent>> {
+        let body = tokio::fs::read_to_string(path).await?;
+        body.lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_str(l).map_err(Into::into))
+            .collect()
+    }
+
+    /// Delete a batch (delivered, evicted or unreadable)
+    pub async fn remove(&self, path: &Path) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Sequence number of a `<seq>.ndjson` batch file
+fn seq_of(path: &Path) -> Option<u64> {
+    if path.extension()? != "ndjson" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::test_event;
+    use chrono::DateTime;
+    use uuid::Uuid;
+
+    fn events(n: usize) -> Vec<DnsEvent> {
+        (0..n)
+            .map(|i| DnsEvent {
+                ts: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                etld1: format!("site{}.com", i),
+                ..test_event()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_write_replay_and_cap() {
+        let dir = std::env::temp_dir().join(format!("hp-dns-gw-spool-{}", Uuid::new_v4()));
+        let dir_str = dir.to_str().unwrap();
+        let batch_bytes = ndjson(&events(2)).unwrap().len() as u64;
+
+        let spool = Spool::open(dir_str, batch_bytes * 2).await.unwrap();
+        let (first, evicted) = spool.write(&events(2)).await.unwrap();
+        assert_eq!(evicted, 0);
+        assert_eq!(spool.read(&first.path).await.unwrap()[1].etld1, "site1.com");
+        spool.write(&events(2)).await.unwrap();
+        // Third batch evicts the oldest
+        let (_, evicted) = spool.write(&events(2)).await.unwrap();
+        assert_eq!(evicted, 2);
+        assert_eq!(spool.entries().await.unwrap().len(), 2);
+        assert!(spool.write(&events(10)).await.is_err());
+
+        // Reopening continues the sequence after the surviving batches
+        let reopened = Spool::open(dir_str, batch_bytes * 2).await.unwrap();
+        let entries = reopened.entries().await.unwrap();
+        assert_eq!(reopened.next_seq.load(Ordering::Relaxed), 3);
+        assert!(entries[0].path.ends_with(format!("{:020}.ndjson", 1)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+//! Tinybird Events API sink (NDJSON over HTTPS)
+
+use super::aggregate::CategoryHistogram;
+use super::{ndjson, sink_http_client, DnsEvent, EventSink};
+use axum::async_trait;
+
+pub struct TinybirdSink {
+    token: String,
+    endpoint: String,
+    client: reqwest::Client,
+}
+
+impl TinybirdSink {
+    pub fn new(token: &str, endpoint: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            client: sink_http_client(),
+        }
+    }
+
+    async fn post(&self, datasource: &str, ndjson: String) -> anyhow::Result<()> {
+        let url = format!("{}/v0/events?name={}", self.endpoint, datasource);
+        let resp = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Content-Type", "application/x-ndjson")
+            .body(ndjson)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("{} - {}", status, body.trim());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for TinybirdSink {
+    fn name(&self) -> &'static str {
+        "tinybird"
+    }
+
+    async fn send_events(&self, events: &[DnsEvent]) -> anyhow::Result<()> {
+        self.post("dns_events", ndjson(events)?).await
+    }
+
+    async fn send_histograms(&self, rows: &[CategoryHistogram]) -> anyhow::Result<()> {
+        self.post("interest_histograms", ndjson(rows)?).await
+    }
+}
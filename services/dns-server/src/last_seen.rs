@@ -30,6 +30,13 @@ impl LastSeenCache {
         self.by_device.read().ok()?.get(device_id).copied()
     }
 
+    /// Forget a deleted device
+    pub fn remove(&self, device_id: &Uuid) {
+        if let Ok(mut map) = self.by_device.write() {
+            map.remove(device_id);
+        }
+    }
+
     /// Check if device was seen within N minutes
     pub fn is_connected(&self, device_id: &Uuid, minutes: i64) -> bool {
         if let Some(last_seen) = self.get(device_id) {
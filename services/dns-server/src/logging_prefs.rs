@@ -0,0 +1,209 @@
+//! Per-user and per-device interest logging preferences
+//!
+//! Lets people use the VPN for blocking without being profiled. Each user
+//! and device has a `LoggingMode` plus an optional pause deadline; the
+//! handler asks `LoggingPrefs::effective` before queuing an event. When both
+//! a user and a device setting exist, the stricter one applies, so "never
+//! log this device" holds regardless of the account-wide choice.
+
+use crate::ingest::DnsEvent;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What gets logged for a user or device (ordered by strictness)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoggingMode {
+    /// Full events (eTLD+1, categories, wallet)
+    #[default]
+    Full,
+    /// Categories only: the domain and its HMAC are stripped
+    CategoriesOnly,
+    /// Nothing is logged
+    Off,
+}
+
+impl LoggingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoggingMode::Full => "full",
+            LoggingMode::CategoriesOnly => "categories_only",
+            LoggingMode::Off => "off",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "full" => Some(LoggingMode::Full),
+            "categories_only" => Some(LoggingMode::CategoriesOnly),
+            "off" => Some(LoggingMode::Off),
+            _ => None,
+        }
+    }
+
+    /// Apply the mode to an event; None means it must not be queued
+    pub fn apply(self, mut event: DnsEvent) -> Option<DnsEvent> {
+        match self {
+            LoggingMode::Full => Some(event),
+            // Without a category there is nothing left worth logging
+            LoggingMode::CategoriesOnly if event.categories.is_empty() => None,
+            LoggingMode::CategoriesOnly => {
+                event.etld1 = String::new();
+                event.domain_hmac = String::new();
+                event.key_id = String::new();
+                Some(event)
+            }
+            LoggingMode::Off => None,
+        }
+    }
+}
+
+/// A stored preference: a mode, optionally suspended until a deadline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggingSettings {
+    pub mode: LoggingMode,
+    /// Logging is off until this time, then `mode` applies again
+    #[serde(default)]
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+impl LoggingSettings {
+    /// The mode in force at `now`
+    pub fn mode_at(&self, now: DateTime<Utc>) -> LoggingMode {
+        match self.paused_until {
+            Some(until) if until > now => LoggingMode::Off,
+            _ => self.mode,
+        }
+    }
+}
+
+/// Logging preferences keyed by (user_id, None) or (user_id, Some(device_id))
+pub struct LoggingPrefs {
+    settings: DashMap<(Uuid, Option<Uuid>), LoggingSettings>,
+}
+
+impl LoggingPrefs {
+    pub fn new() -> Self {
+        Self {
+            settings: DashMap::new(),
+        }
+    }
+
+    /// Load non-default preferences from database at startup
+    pub async fn load_from_db(&self, db: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query_as::<_, (Uuid, Option<Uuid>, String, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT id, NULL::uuid, logging_mode, logging_paused_until FROM users
+            WHERE logging_mode <> 'full' OR logging_paused_until > NOW()
+            UNION ALL
+            SELECT user_id, id, logging_mode, logging_paused_until FROM devices
+            WHERE logging_mode <> 'full' OR logging_paused_until > NOW()
+            "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        self.settings.clear();
+        for (user_id, device_id, mode, paused_until) in rows {
+            let Some(mode) = LoggingMode::parse(&mode) else {
+                tracing::warn!(user_id = %user_id, mode = %mode, "Skipping unknown logging mode");
+                continue;
+            };
+            self.settings.insert((user_id, device_id), LoggingSettings { mode, paused_until });
+        }
+
+        tracing::info!(scopes = self.settings.len(), "Logging preferences loaded");
+        Ok(())
+    }
+
+    /// Get the stored settings for a user (device_id None) or device
+    pub fn get(&self, user_id: Uuid, device_id: Option<Uuid>) -> LoggingSettings {
+        self.settings
+            .get(&(user_id, device_id))
+            .map(|s| *s)
+            .unwrap_or_default()
+    }
+
+    /// Replace the settings for a user or device
+    pub fn set(&self, user_id: Uuid, device_id: Option<Uuid>, settings: LoggingSettings) {
+        if settings == LoggingSettings::default() {
+            self.settings.remove(&(user_id, device_id));
+        } else {
+            self.settings.insert((user_id, device_id), settings);
+        }
+    }
+
+    /// Forget a deleted device's settings
+    pub fn remove_device(&self, user_id: Uuid, device_id: Uuid) {
+        self.settings.remove(&(user_id, Some(device_id)));
+    }
+
+    /// The mode in force right now: the stricter of the user's and the
+    /// device's (a nil device_id, e.g. DoH, only sees the user's)
+    pub fn effective(&self, user_id: Uuid, device_id: Uuid) -> LoggingMode {
+        if self.settings.is_empty() {
+            return LoggingMode::Full;
+        }
+        let now = Utc::now();
+        let user = self.get(user_id, None).mode_at(now);
+        if device_id.is_nil() {
+            return user;
+        }
+        user.max(self.get(user_id, Some(device_id)).mode_at(now))
+    }
+}
+
+impl Default for LoggingPrefs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_stricter_of_user_and_device() {
+        let prefs = LoggingPrefs::new();
+        let (user, laptop, phone) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        prefs.set(user, None, LoggingSettings { mode: LoggingMode::CategoriesOnly, paused_until: None });
+        prefs.set(user, Some(phone), LoggingSettings { mode: LoggingMode::Off, paused_until: None });
+
+        assert_eq!(prefs.effective(user, laptop), LoggingMode::CategoriesOnly);
+        assert_eq!(prefs.effective(user, phone), LoggingMode::Off);
+        assert_eq!(prefs.effective(user, Uuid::nil()), LoggingMode::CategoriesOnly);
+        assert_eq!(prefs.effective(Uuid::new_v4(), laptop), LoggingMode::Full);
+    }
+
+    #[test]
+    fn test_pause_expires() {
+        let now = Utc::now();
+        let paused = LoggingSettings {
+            mode: LoggingMode::CategoriesOnly,
+            paused_until: Some(now + Duration::hours(1)),
+        };
+        assert_eq!(paused.mode_at(now), LoggingMode::Off);
+        assert_eq!(paused.mode_at(now + Duration::hours(2)), LoggingMode::CategoriesOnly);
+    }
+
+    #[test]
+    fn test_categories_only_strips_domain() {
+        let event = DnsEvent {
+            device_id: Uuid::new_v4(),
+            etld1: "spotify.com".to_string(),
+            categories: vec![crate::categorize::CategoryWeight { category_id: 3, weight: 1.0 }],
+            ..crate::ingest::test_event()
+        };
+        let stripped = LoggingMode::CategoriesOnly.apply(event.clone()).unwrap();
+        assert!(stripped.etld1.is_empty() && stripped.domain_hmac.is_empty());
+        assert_eq!(stripped.wallet_id, "0xabc");
+
+        let uncategorized = DnsEvent { categories: vec![], ..event.clone() };
+        assert!(LoggingMode::CategoriesOnly.apply(uncategorized).is_none());
+        assert!(LoggingMode::Off.apply(event).is_none());
+    }
+}
@@ -1,14 +1,17 @@
 //! hp-dns-gw - DNS Gateway for Interest-Based Dating
 //!
-//! Logs per-wallet DNS queries to Tinybird for interest matching.
+//! Logs per-wallet DNS queries to an event sink (Tinybird by default) for interest matching.
 
 mod api;
 mod auth;
+mod blocklists;
 mod categorize;
 mod config;
 mod dns;
 mod ingest;
 mod last_seen;
+mod logging_prefs;
+mod metrics;
 mod rules;
 mod users;
 
@@ -54,9 +57,29 @@ async fn main() -> Result<()> {
         tracing::warn!("Failed to hydrate rules cache from DB: {} (continuing without cache)", e);
     }
 
+    // Interest logging preferences (opt-out / pause)
+    let logging_prefs = logging_prefs::LoggingPrefs::new();
+    if let Err(e) = logging_prefs.load_from_db(&db).await {
+        tracing::warn!("Failed to hydrate logging preferences from DB: {} (continuing with defaults)", e);
+    }
+
+    // Community blocklists (lists load in the background refresher)
+    let blocklists = blocklists::BlocklistManager::new(blocklists::parse_sources(&config.blocklists)?);
+    if let Err(e) = blocklists.load_subscriptions(&db).await {
+        tracing::warn!("Failed to hydrate blocklist subscriptions from DB: {} (continuing without)", e);
+    }
+
     // Auth state
     let auth = auth::AuthState::new(&config.jwt_secret, &config.auth_domain);
 
+    // Upstream resolver client (pooled sockets, TCP fallback, failover)
+    let upstream = dns::upstream::UpstreamClient::connect(
+        &dns::upstream::parse_upstreams(&config.upstream_dns)?,
+        config.upstream_sockets,
+        std::time::Duration::from_millis(config.upstream_timeout_ms),
+    )
+    .await?;
+
     // Heaven resolver (optional - only if HEAVEN_API_URL is set)
     let heaven = config.heaven_api_url.as_ref().map(|url| {
         let gateway_ip: Ipv4Addr = config
@@ -67,16 +90,54 @@ async fn main() -> Result<()> {
         HeavenResolver::new(url.clone(), config.heaven_dns_secret.clone(), gateway_ip)
     });
 
+    // Event ingest (raw events, or aggregated histograms only)
+    let sink = ingest::build_sink(&config, &db)?;
+    tracing::info!(sink = sink.name(), "Event sink configured");
+    let mut events = ingest::EventQueue::new(
+        sink,
+        ingest::privacy::PrivacyPolicy::parse(&config.sensitive_category_policy)?,
+        config.event_batch_size(),
+    );
+    if config.dedup_window_secs > 0 {
+        events = events.with_dedup(ingest::dedup::Deduplicator::new(
+            config.dedup_window_secs,
+            config.session_gap_secs,
+        ));
+    }
+    if let Some(dir) = &config.event_spool_dir {
+        let spool = ingest::spool::Spool::open(dir, config.event_spool_max_bytes).await?;
+        tracing::info!(dir = %dir, pending = spool.entries().await?.len(), "Event spool enabled");
+        events = events.with_spool(spool);
+    }
+    if config.ingest_mode == ingest::IngestMode::Aggregate {
+        tracing::info!(
+            interval_secs = config.aggregate_interval_secs,
+            epsilon = ?config.aggregate_dp_epsilon,
+            "Ingest in aggregate mode"
+        );
+        events = events.with_aggregator(ingest::aggregate::InterestAggregator::new(config.aggregate_dp_epsilon));
+    }
+
     // Shared state
     let state = Arc::new(AppState {
         config: config.clone(),
         db,
         user_cache,
         rules_cache,
+        blocklists,
         auth,
-        category_map: categorize::CategoryMap::load()?,
-        tinybird: ingest::TinybirdClient::new(&config.tinybird_token, &config.tinybird_endpoint),
+        category_map: categorize::CategoryMap::load(config.category_map_path.as_deref())?,
+        events,
+        hmac_keys: ingest::hmac_keys::HmacKeyring::new(
+            &config.hmac_secret,
+            config.hmac_keys.as_deref(),
+            config.hmac_epoch_secs,
+        )?,
         last_seen: last_seen::LastSeenCache::new(),
+        logging_prefs,
+        upstream,
+        response_cache: dns::cache::ResponseCache::new(config.dns_cache_max_bytes),
+        metrics: metrics::Metrics::new(),
         heaven,
     });
 
@@ -101,19 +162,57 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Start Tinybird batch sender
+    // Start block page (sinkhole mode target, optional)
+    let block_page_shutdown = shutdown_tx.subscribe();
+    let block_page_state = state.clone();
+    let block_page_handle = tokio::spawn(async move {
+        if let Err(e) = api::block_page::run(block_page_state, block_page_shutdown).await {
+            tracing::error!("Block page server error: {}", e);
+        }
+    });
+
+    // Start event batch sender
     let ingest_shutdown = shutdown_tx.subscribe();
     let ingest_state = state.clone();
     let ingest_handle = tokio::spawn(async move {
         ingest::batch_sender(ingest_state, ingest_shutdown).await;
     });
 
+    // Start blocklist refresher
+    let blocklist_shutdown = shutdown_tx.subscribe();
+    let blocklist_state = state.clone();
+    let blocklist_handle = tokio::spawn(async move {
+        blocklists::refresh_loop(blocklist_state, blocklist_shutdown).await;
+    });
+
+    // Sweep expired responses out of the DNS cache
+    let cache_shutdown = shutdown_tx.subscribe();
+    let cache_state = state.clone();
+    let cache_handle = tokio::spawn(async move {
+        dns::cache::sweep_loop(cache_state, cache_shutdown).await;
+    });
+
+    // Reload the category map on SIGHUP
+    let sighup_shutdown = shutdown_tx.subscribe();
+    let sighup_state = state.clone();
+    let sighup_handle = tokio::spawn(async move {
+        categorize::reload_on_sighup(sighup_state, sighup_shutdown).await;
+    });
+
     // Wait for shutdown
     tokio::signal::ctrl_c().await?;
     tracing::info!("Shutdown signal received");
     let _ = shutdown_tx.send(());
 
-    let _ = tokio::join!(dns_handle, api_handle, ingest_handle);
+    let _ = tokio::join!(
+        dns_handle,
+        api_handle,
+        block_page_handle,
+        ingest_handle,
+        blocklist_handle,
+        cache_handle,
+        sighup_handle
+    );
     tracing::info!("hp-dns-gw stopped");
 
     Ok(())
@@ -125,10 +224,16 @@ pub struct AppState {
     pub db: sqlx::PgPool,
     pub user_cache: users::UserCache,
     pub rules_cache: rules::RulesCache,
+    pub blocklists: blocklists::BlocklistManager,
     pub auth: auth::AuthState,
     pub category_map: categorize::CategoryMap,
-    pub tinybird: ingest::TinybirdClient,
+    pub events: ingest::EventQueue,
+    pub hmac_keys: ingest::hmac_keys::HmacKeyring,
     pub last_seen: last_seen::LastSeenCache,
+    pub logging_prefs: logging_prefs::LoggingPrefs,
+    pub upstream: dns::upstream::UpstreamClient,
+    pub response_cache: dns::cache::ResponseCache,
+    pub metrics: metrics::Metrics,
     /// Optional .heaven TLD resolver (enabled when HEAVEN_API_URL is set)
     pub heaven: Option<HeavenResolver>,
 }
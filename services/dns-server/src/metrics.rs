@@ -0,0 +1,228 @@
+//! Prometheus text-format metrics (`GET /metrics`)
+//!
+//! Counters live next to what they count: query counts and open TCP
+//! connections here, upstream and Heaven API latency on their clients,
+//! cache sizes on the caches, delivery counters on the event queue.
+//! `render` collects them all at scrape time.
+
+use crate::AppState;
+use dashmap::DashMap;
+use hickory_proto::rr::RecordType;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Latency bucket upper bounds in seconds
+const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Lock-free latency histogram
+pub struct Histogram {
+    /// Per-bucket (non-cumulative) counts; the last slot is +Inf
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let idx = LATENCY_BUCKETS
+            .iter()
+            .position(|b| secs <= *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        let mut cumulative = 0;
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += self.buckets[i].load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, sum, name, cumulative);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gateway-level counters
+pub struct Metrics {
+    /// (action, qtype) -> queries answered
+    queries: DashMap<(&'static str, &'static str), AtomicU64>,
+    /// Open DNS-over-TCP and DoT connections
+    tcp_connections: AtomicI64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            queries: DashMap::new(),
+            tcp_connections: AtomicI64::new(0),
+        }
+    }
+
+    /// Count an answered query (action: allow, block, heaven or error)
+    pub fn record_query(&self, action: &'static str, qtype: RecordType) {
+        let key = (action, qtype_label(qtype));
+        if let Some(counter) = self.queries.get(&key) {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.queries
+            .entry(key)
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Track an open TCP connection until the guard drops
+    pub fn tcp_connection(&self) -> TcpConnectionGuard<'_> {
+        self.tcp_connections.fetch_add(1, Ordering::Relaxed);
+        TcpConnectionGuard { metrics: self }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct TcpConnectionGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for TcpConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.tcp_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Bounded qtype label set (arbitrary numeric types collapse into "other")
+fn qtype_label(qtype: RecordType) -> &'static str {
+    match qtype {
+        RecordType::A => "A",
+        RecordType::AAAA => "AAAA",
+        RecordType::CNAME => "CNAME",
+        RecordType::HTTPS => "HTTPS",
+        RecordType::SVCB => "SVCB",
+        RecordType::MX => "MX",
+        RecordType::NS => "NS",
+        RecordType::PTR => "PTR",
+        RecordType::SOA => "SOA",
+        RecordType::SRV => "SRV",
+        RecordType::TXT => "TXT",
+        RecordType::CAA => "CAA",
+        _ => "other",
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+/// Render every metric in Prometheus text exposition format
+pub async fn render(state: &AppState) -> String {
+    let mut out = String::new();
+    let m = &state.metrics;
+
+    let _ = writeln!(
+        out,
+        "# HELP hp_dns_queries_total DNS queries answered, by action and qtype\n# TYPE hp_dns_queries_total counter"
+    );
+    let mut queries: Vec<_> = m
+        .queries
+        .iter()
+        .map(|e| (*e.key(), e.value().load(Ordering::Relaxed)))
+        .collect();
+    queries.sort();
+    for ((action, qtype), n) in queries {
+        let _ = writeln!(out, "hp_dns_queries_total{{action=\"{}\",qtype=\"{}\"}} {}", action, qtype, n);
+    }
+
+    state.upstream.latency().render(
+        &mut out,
+        "hp_dns_upstream_latency_seconds",
+        "Upstream resolver round trip, including failover",
+    );
+    gauge(&mut out, "hp_dns_upstreams_down", "Upstream resolvers currently marked down", state.upstream.down_count());
+    if let Some(heaven) = &state.heaven {
+        heaven.api_latency().render(&mut out, "hp_dns_heaven_api_latency_seconds", "Heaven Names API calls");
+        gauge(&mut out, "hp_dns_heaven_cache_entries", "Cached .heaven names", heaven.cache_len());
+    }
+
+    gauge(&mut out, "hp_dns_tcp_connections", "Open DNS-over-TCP/TLS connections", m.tcp_connections.load(Ordering::Relaxed));
+    gauge(&mut out, "hp_dns_user_cache_entries", "Users cached by VPN IP", state.user_cache.len());
+    gauge(&mut out, "hp_dns_rules_cache_scopes", "User and device rule sets cached", state.rules_cache.len().await);
+    gauge(&mut out, "hp_dns_response_cache_entries", "Cached upstream responses", state.response_cache.len());
+    gauge(&mut out, "hp_dns_response_cache_bytes", "Bytes held by the response cache", state.response_cache.bytes());
+    counter(&mut out, "hp_dns_response_cache_hits_total", "Response cache hits", state.response_cache.hits());
+    counter(&mut out, "hp_dns_response_cache_misses_total", "Response cache misses", state.response_cache.misses());
+
+    let ingest = state.events.stats();
+    gauge(&mut out, "hp_dns_ingest_queue_length", "Events waiting to be sent", state.events.queue_len());
+    counter(&mut out, "hp_dns_ingest_flushes_total", "Event batches delivered to the sink", ingest.flushes);
+    counter(&mut out, "hp_dns_ingest_flush_failures_total", "Event batches that failed after retries", ingest.flush_failures);
+    counter(&mut out, "hp_dns_ingest_spooled_events_total", "Events left in the spool after failed delivery", ingest.spooled);
+    counter(&mut out, "hp_dns_ingest_replayed_events_total", "Events delivered from the spool", ingest.replayed);
+    counter(&mut out, "hp_dns_ingest_dropped_events_total", "Events discarded before delivery", ingest.dropped);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let h = Histogram::new();
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_millis(20));
+        h.observe(Duration::from_secs(10));
+
+        let mut out = String::new();
+        h.render(&mut out, "x_seconds", "test");
+        assert!(out.contains("x_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("x_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(out.contains("x_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("x_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("x_seconds_count 3\n"));
+        assert!(out.contains("x_seconds_sum 10.0205\n"));
+    }
+
+    #[test]
+    fn test_query_counters_and_connections() {
+        let m = Metrics::new();
+        m.record_query("allow", RecordType::A);
+        m.record_query("allow", RecordType::A);
+        m.record_query("block", RecordType::Unknown(65280));
+        assert_eq!(m.queries.get(&("allow", "A")).unwrap().load(Ordering::Relaxed), 2);
+        assert_eq!(m.queries.get(&("block", "other")).unwrap().load(Ordering::Relaxed), 1);
+
+        {
+            let _a = m.tcp_connection();
+            let _b = m.tcp_connection();
+            assert_eq!(m.tcp_connections.load(Ordering::Relaxed), 2);
+        }
+        assert_eq!(m.tcp_connections.load(Ordering::Relaxed), 0);
+    }
+}
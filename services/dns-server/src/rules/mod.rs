@@ -1,114 +1,528 @@
 //! User rules cache for DNS blocking
 //!
-//! Maintains an in-memory cache of blocked domains per user,
+//! Maintains an in-memory cache of typed rules per user,
 //! loaded from Postgres and updated via API.
+//!
+//! Rule kinds:
+//! - `exact`: blocks only the listed name
+//! - `suffix`: blocks the name and all its subdomains (the original behaviour)
+//! - `glob`: blocks names matching a pattern, where `*` matches any run of
+//!   characters (including dots) and `?` a single character, e.g. `*.ads.*`
+//! - `allow`: exempts the name and its subdomains from every block rule
+//! - `category`: blocks every domain `CategoryMap` assigns to a category;
+//!   `domain` holds the category slug (e.g. `social`). Combine with allow
+//!   rules for "block all Social except linkedin.com".
+//!
+//! Precedence in `verdict`: allow > exact > suffix > glob > category. A
+//! user's own rules are consulted before any subscribed blocklist.
+//!
+//! Any rule may carry a schedule (days of week plus a time window in an IANA
+//! timezone), e.g. block `reddit.com` Mon-Fri 09:00-17:00 Europe/Berlin. A
+//! scheduled rule only takes part in `verdict` while its window is active,
+//! ranking by its kind like an unscheduled one.
+//!
+//! Rules are either user-wide or scoped to one device. Device rules are
+//! evaluated first, so a device can allow what the user blocks elsewhere
+//! (or block what is allowed user-wide).
+//!
+//! How a blocked name is answered (`BlockMode`) comes from the matching rule,
+//! else the user's default, else the server-wide `BLOCK_MODE`.
 
-use std::collections::{HashMap, HashSet};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use crate::categorize::Category;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
-/// Cache of blocked domains per user
+/// How a rule's pattern is matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleKind {
+    Exact,
+    Suffix,
+    Glob,
+    Allow,
+    Category,
+}
+
+impl RuleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleKind::Exact => "exact",
+            RuleKind::Suffix => "suffix",
+            RuleKind::Glob => "glob",
+            RuleKind::Allow => "allow",
+            RuleKind::Category => "category",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "exact" => Some(RuleKind::Exact),
+            "suffix" => Some(RuleKind::Suffix),
+            "glob" => Some(RuleKind::Glob),
+            "allow" => Some(RuleKind::Allow),
+            "category" => Some(RuleKind::Category),
+            _ => None,
+        }
+    }
+}
+
+/// How a blocked query is answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockMode {
+    /// 0.0.0.0 / :: for A/AAAA
+    #[value(name = "null_ip")]
+    NullIp,
+    /// NXDOMAIN for every query type
+    Nxdomain,
+    /// REFUSED for every query type
+    Refused,
+    /// A/AAAA point at the block page host (BLOCK_SINKHOLE_IPV4/IPV6)
+    Sinkhole,
+}
+
+impl BlockMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockMode::NullIp => "null_ip",
+            BlockMode::Nxdomain => "nxdomain",
+            BlockMode::Refused => "refused",
+            BlockMode::Sinkhole => "sinkhole",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "null_ip" => Some(BlockMode::NullIp),
+            "nxdomain" => Some(BlockMode::Nxdomain),
+            "refused" => Some(BlockMode::Refused),
+            "sinkhole" => Some(BlockMode::Sinkhole),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of checking a name against a user's rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// An allow rule matched
+    Allow,
+    /// A block rule matched; the mode is the rule's own, or the user's default
+    Block(Option<BlockMode>),
+}
+
+/// Weekly window during which a rule applies
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Days the window starts on (empty = every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Window start, "HH:MM" local time
+    #[serde(with = "hhmm")]
+    pub start: NaiveTime,
+    /// Window end, "HH:MM" local time; before `start` means the window spans midnight
+    #[serde(with = "hhmm")]
+    pub end: NaiveTime,
+    /// IANA timezone the window is evaluated in, e.g. "America/New_York"
+    pub timezone: Tz,
+}
+
+impl Schedule {
+    /// Whether the window is open at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        let (day, time) = (local.weekday(), local.time());
+        let on = |d: Weekday| self.days.is_empty() || self.days.contains(&d);
+
+        if self.start <= self.end {
+            on(day) && time >= self.start && time < self.end
+        } else {
+            // Overnight window: the evening part belongs to today, the early hours to yesterday
+            (on(day) && time >= self.start) || (on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+/// "HH:MM" (de)serialization for schedule times
+mod hhmm {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(t: &NaiveTime, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&t.format("%H:%M").to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveTime, D::Error> {
+        let s = String::deserialize(d)?;
+        NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+    }
+}
+
+/// A single user rule as stored in `user_rules`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rule {
+    pub domain: String,
+    pub kind: RuleKind,
+    /// Only applies during this window (always applies when None)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    /// Overrides the user's default block response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_mode: Option<BlockMode>,
+}
+
+impl Rule {
+    /// Normalize and validate a rule from user input
+    pub fn new(domain: &str, kind: RuleKind, schedule: Option<Schedule>) -> Result<Self, String> {
+        let domain = domain.trim().trim_end_matches('.').to_lowercase();
+        if domain.is_empty() {
+            return Err("Empty rule domain".to_string());
+        }
+        if schedule.as_ref().is_some_and(|s| s.start == s.end) {
+            return Err(format!("Schedule start and end must differ: {}", domain));
+        }
+        if kind == RuleKind::Category {
+            return match Category::from_slug(&domain) {
+                Some(c) if c != Category::Unknown => Ok(Self {
+                    domain,
+                    kind,
+                    schedule,
+                    block_mode: None,
+                }),
+                _ => Err(format!("Unknown category: {}", domain)),
+            };
+        }
+
+        let is_pattern = domain.contains(['*', '?']);
+        if kind == RuleKind::Glob && !is_pattern {
+            return Err(format!("Glob rule must contain '*' or '?': {}", domain));
+        }
+        if kind != RuleKind::Glob && is_pattern {
+            return Err(format!("Wildcards are only allowed in glob rules: {}", domain));
+        }
+
+        Ok(Self {
+            domain,
+            kind,
+            schedule,
+            block_mode: None,
+        })
+    }
+
+    /// Set the block response for this rule (meaningless on allow rules)
+    pub fn with_block_mode(mut self, block_mode: Option<BlockMode>) -> Result<Self, String> {
+        if block_mode.is_some() && self.kind == RuleKind::Allow {
+            return Err(format!("Allow rules cannot set a block mode: {}", self.domain));
+        }
+        self.block_mode = block_mode;
+        Ok(self)
+    }
+
+    /// Whether `other` is the same rule, ignoring its block mode
+    fn same_rule(&self, other: &Rule) -> bool {
+        self.domain == other.domain && self.kind == other.kind && self.schedule == other.schedule
+    }
+
+    /// Whether this rule's pattern covers `domain` in `category` (ignores schedule)
+    fn matches(&self, domain: &str, category: Option<i32>) -> bool {
+        match self.kind {
+            RuleKind::Category => category
+                .and_then(Category::from_id)
+                .is_some_and(|c| c.slug() == self.domain),
+            RuleKind::Exact => self.domain == domain,
+            RuleKind::Suffix | RuleKind::Allow => {
+                domain == self.domain
+                    || domain
+                        .strip_suffix(self.domain.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }
+            RuleKind::Glob => glob_match(&self.domain, domain),
+        }
+    }
+}
+
+/// Compiled rules for one user, keyed by pattern
+#[derive(Default)]
+struct UserRules {
+    exact: HashMap<String, Rule>,
+    suffix: HashMap<String, Rule>,
+    glob: Vec<Rule>,
+    allow: HashMap<String, Rule>,
+    /// Blocked category IDs
+    categories: HashMap<i32, Rule>,
+    /// Rules with a schedule, checked one by one against the clock
+    scheduled: Vec<Rule>,
+}
+
+impl UserRules {
+    fn insert(&mut self, rule: Rule) {
+        if rule.schedule.is_some() {
+            self.scheduled.retain(|r| !r.same_rule(&rule));
+            self.scheduled.push(rule);
+            return;
+        }
+        match rule.kind {
+            RuleKind::Exact => {
+                self.exact.insert(rule.domain.clone(), rule);
+            }
+            RuleKind::Suffix => {
+                self.suffix.insert(rule.domain.clone(), rule);
+            }
+            RuleKind::Glob => {
+                self.glob.retain(|g| g.domain != rule.domain);
+                self.glob.push(rule);
+            }
+            RuleKind::Allow => {
+                self.allow.insert(rule.domain.clone(), rule);
+            }
+            RuleKind::Category => {
+                if let Some(c) = Category::from_slug(&rule.domain) {
+                    self.categories.insert(c as i32, rule);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, rule: &Rule) {
+        if rule.schedule.is_some() {
+            self.scheduled.retain(|r| !r.same_rule(rule));
+            return;
+        }
+        match rule.kind {
+            RuleKind::Exact => {
+                self.exact.remove(&rule.domain);
+            }
+            RuleKind::Suffix => {
+                self.suffix.remove(&rule.domain);
+            }
+            RuleKind::Glob => self.glob.retain(|g| g.domain != rule.domain),
+            RuleKind::Allow => {
+                self.allow.remove(&rule.domain);
+            }
+            RuleKind::Category => {
+                if let Some(c) = Category::from_slug(&rule.domain) {
+                    self.categories.remove(&(c as i32));
+                }
+            }
+        }
+    }
+
+    fn to_rules(&self) -> Vec<Rule> {
+        self.exact
+            .values()
+            .chain(self.suffix.values())
+            .chain(&self.glob)
+            .chain(self.allow.values())
+            .chain(self.categories.values())
+            .chain(&self.scheduled)
+            .cloned()
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.exact.len()
+            + self.suffix.len()
+            + self.glob.len()
+            + self.allow.len()
+            + self.categories.len()
+            + self.scheduled.len()
+    }
+
+    /// The verdict of the highest-precedence matching rule, None if no rule applies
+    fn verdict(&self, domain: &str, category: Option<i32>, now: DateTime<Utc>) -> Option<Verdict> {
+        // Scheduled rules whose window is open right now and that cover this domain
+        let active: Vec<&Rule> = self
+            .scheduled
+            .iter()
+            .filter(|r| {
+                r.matches(domain, category) && r.schedule.as_ref().is_some_and(|s| s.is_active(now))
+            })
+            .collect();
+
+        if find_suffix(&self.allow, domain).is_some() || active.iter().any(|r| r.kind == RuleKind::Allow) {
+            return Some(Verdict::Allow);
+        }
+        let active_of = |kind: RuleKind| active.iter().copied().find(|r| r.kind == kind);
+        let blocking = self
+            .exact
+            .get(domain)
+            .or_else(|| active_of(RuleKind::Exact))
+            .or_else(|| find_suffix(&self.suffix, domain))
+            .or_else(|| active_of(RuleKind::Suffix))
+            .or_else(|| self.glob.iter().find(|g| glob_match(&g.domain, domain)))
+            .or_else(|| active_of(RuleKind::Glob))
+            .or_else(|| category.and_then(|c| self.categories.get(&c)))
+            .or_else(|| active_of(RuleKind::Category))?;
+        Some(Verdict::Block(blocking.block_mode))
+    }
+
+    #[cfg(test)]
+    fn is_blocked(&self, domain: &str) -> bool {
+        matches!(self.verdict(domain, None, Utc::now()), Some(Verdict::Block(_)))
+    }
+}
+
+/// Rule scope: (user_id, None) for user-wide rules, (user_id, Some(device_id)) for device rules
+type Scope = (Uuid, Option<Uuid>);
+
+/// Cache of typed rules per user and device
 pub struct RulesCache {
-    /// user_id -> set of blocked domains (normalized, lowercase)
-    blocked: Arc<RwLock<HashMap<Uuid, HashSet<String>>>>,
+    /// scope -> compiled rules (normalized, lowercase)
+    rules: Arc<RwLock<HashMap<Scope, UserRules>>>,
+    /// user_id -> default block response (`users.block_mode`)
+    block_modes: Arc<RwLock<HashMap<Uuid, BlockMode>>>,
 }
 
 impl RulesCache {
     pub fn new() -> Self {
         Self {
-            blocked: Arc::new(RwLock::new(HashMap::new())),
+            rules: Arc::new(RwLock::new(HashMap::new())),
+            block_modes: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    /// Number of cached rule sets (user-wide and per-device)
+    pub async fn len(&self) -> usize {
+        self.rules.read().await.len()
+    }
+
     /// Load rules from database at startup
     pub async fn load_from_db(&self, db: &sqlx::PgPool) -> Result<(), sqlx::Error> {
-        let rows = sqlx::query_as::<_, (Uuid, String)>(
-            "SELECT user_id, domain FROM user_rules"
+        let rows = sqlx::query_as::<_, (Uuid, Option<Uuid>, String, String, Option<String>, Option<String>)>(
+            "SELECT user_id, device_id, domain, kind, schedule::text, block_mode FROM user_rules"
+        )
+        .fetch_all(db)
+        .await?;
+
+        let modes = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, block_mode FROM users WHERE block_mode IS NOT NULL"
         )
         .fetch_all(db)
         .await?;
 
-        let mut cache = self.blocked.write().await;
+        let mut block_modes = self.block_modes.write().await;
+        block_modes.clear();
+        block_modes.extend(modes.into_iter().filter_map(|(id, m)| Some((id, BlockMode::parse(&m)?))));
+
+        let mut cache = self.rules.write().await;
         cache.clear();
 
-        for (user_id, domain) in rows {
-            cache
-                .entry(user_id)
-                .or_insert_with(HashSet::new)
-                .insert(domain.to_lowercase());
+        for (user_id, device_id, domain, kind, schedule, block_mode) in rows {
+            let Some(kind) = RuleKind::parse(&kind) else {
+                tracing::warn!(user_id = %user_id, kind = %kind, "Skipping rule with unknown kind");
+                continue;
+            };
+            let schedule = match schedule.as_deref().map(serde_json::from_str::<Schedule>) {
+                None => None,
+                Some(Ok(s)) => Some(s),
+                Some(Err(e)) => {
+                    tracing::warn!(user_id = %user_id, domain = %domain, error = %e, "Skipping rule with invalid schedule");
+                    continue;
+                }
+            };
+            cache.entry((user_id, device_id)).or_default().insert(Rule {
+                domain: domain.to_lowercase(),
+                kind,
+                schedule,
+                block_mode: block_mode.as_deref().and_then(BlockMode::parse),
+            });
         }
 
         tracing::info!(
-            users = cache.len(),
-            total_rules = cache.values().map(|s| s.len()).sum::<usize>(),
+            scopes = cache.len(),
+            total_rules = cache.values().map(|r| r.len()).sum::<usize>(),
             "Rules cache loaded"
         );
 
         Ok(())
     }
 
-    /// Check if a domain is blocked for a user
-    /// Also checks parent domains (e.g., blocks "xxx.example.com" if "example.com" is blocked)
-    pub async fn is_blocked(&self, user_id: &Uuid, domain: &str) -> bool {
-        let cache = self.blocked.read().await;
-
-        if let Some(blocked_set) = cache.get(user_id) {
-            let domain_lower = domain.to_lowercase();
-
-            // Check exact match
-            if blocked_set.contains(&domain_lower) {
-                return true;
-            }
+    /// Check a domain (full qname, plus its `CategoryMap` category) against a
+    /// user's own rules, merging the device's rules (checked first) with
+    /// user-wide ones. A nil device_id (e.g. DoH) only sees user-wide rules.
+    /// Returns None if no rule applies (subscribed blocklists decide). A
+    /// block verdict carries the rule's mode, falling back to the user's default.
+    pub async fn verdict(
+        &self,
+        user_id: &Uuid,
+        device_id: &Uuid,
+        domain: &str,
+        category: Option<i32>,
+    ) -> Option<Verdict> {
+        let domain = domain.to_lowercase();
+        let now = Utc::now();
+        let verdict = {
+            let cache = self.rules.read().await;
+            let device = (!device_id.is_nil())
+                .then(|| cache.get(&(*user_id, Some(*device_id))))
+                .flatten()
+                .and_then(|r| r.verdict(&domain, category, now));
+            device.or_else(|| cache.get(&(*user_id, None))?.verdict(&domain, category, now))?
+        };
 
-            // Check parent domains (e.g., if "example.com" is blocked, "sub.example.com" is also blocked)
-            let mut parts: Vec<&str> = domain_lower.split('.').collect();
-            while parts.len() > 1 {
-                parts.remove(0);
-                let parent = parts.join(".");
-                if blocked_set.contains(&parent) {
-                    return true;
-                }
-            }
+        match verdict {
+            Verdict::Block(None) => Some(Verdict::Block(self.block_mode(user_id).await)),
+            v => Some(v),
         }
+    }
+
+    /// A user's default block response, if they chose one
+    pub async fn block_mode(&self, user_id: &Uuid) -> Option<BlockMode> {
+        self.block_modes.read().await.get(user_id).copied()
+    }
 
-        false
+    /// Set (or clear) a user's default block response
+    pub async fn set_block_mode(&self, user_id: Uuid, mode: Option<BlockMode>) {
+        let mut modes = self.block_modes.write().await;
+        match mode {
+            Some(m) => modes.insert(user_id, m),
+            None => modes.remove(&user_id),
+        };
     }
 
-    /// Get all blocked domains for a user
-    pub async fn get_blocked_domains(&self, user_id: &Uuid) -> Vec<String> {
-        let cache = self.blocked.read().await;
+    /// Get rules for a user (device_id None) or one of their devices
+    pub async fn get_rules(&self, user_id: &Uuid, device_id: Option<Uuid>) -> Vec<Rule> {
+        let cache = self.rules.read().await;
         cache
-            .get(user_id)
-            .map(|s| s.iter().cloned().collect())
+            .get(&(*user_id, device_id))
+            .map(|r| r.to_rules())
             .unwrap_or_default()
     }
 
-    /// Set blocked domains for a user (replaces existing)
-    pub async fn set_blocked_domains(&self, user_id: Uuid, domains: Vec<String>) {
-        let mut cache = self.blocked.write().await;
-        let set: HashSet<String> = domains.into_iter().map(|d| d.to_lowercase()).collect();
-        cache.insert(user_id, set);
+    /// Set rules for a user or device scope (replaces existing)
+    pub async fn set_rules(&self, user_id: Uuid, device_id: Option<Uuid>, rules: Vec<Rule>) {
+        let mut compiled = UserRules::default();
+        for rule in rules {
+            compiled.insert(rule);
+        }
+        self.rules.write().await.insert((user_id, device_id), compiled);
     }
 
-    /// Add a single blocked domain for a user
+    /// Add a single rule for a user or device scope
     #[allow(dead_code)]
-    pub async fn add_blocked_domain(&self, user_id: Uuid, domain: String) {
-        let mut cache = self.blocked.write().await;
-        cache
-            .entry(user_id)
-            .or_insert_with(HashSet::new)
-            .insert(domain.to_lowercase());
+    pub async fn add_rule(&self, user_id: Uuid, device_id: Option<Uuid>, rule: Rule) {
+        let mut cache = self.rules.write().await;
+        cache.entry((user_id, device_id)).or_default().insert(rule);
     }
 
-    /// Remove a single blocked domain for a user
+    /// Remove a single rule for a user or device scope
     #[allow(dead_code)]
-    pub async fn remove_blocked_domain(&self, user_id: &Uuid, domain: &str) {
-        let mut cache = self.blocked.write().await;
-        if let Some(set) = cache.get_mut(user_id) {
-            set.remove(&domain.to_lowercase());
+    pub async fn remove_rule(&self, user_id: &Uuid, device_id: Option<Uuid>, rule: &Rule) {
+        let mut cache = self.rules.write().await;
+        if let Some(rules) = cache.get_mut(&(*user_id, device_id)) {
+            rules.remove(rule);
         }
     }
+
+    /// Drop all rules scoped to a device (user-wide rules are kept)
+    pub async fn remove_device(&self, user_id: &Uuid, device_id: &Uuid) {
+        self.rules.write().await.remove(&(*user_id, Some(*device_id)));
+    }
 }
 
 impl Default for RulesCache {
@@ -116,3 +530,224 @@ impl Default for RulesCache {
         Self::new()
     }
 }
+
+/// The rule for `domain` or its closest listed parent in `map`
+fn find_suffix<'a>(map: &'a HashMap<String, Rule>, domain: &str) -> Option<&'a Rule> {
+    if map.is_empty() {
+        return None;
+    }
+    let mut rest = domain;
+    loop {
+        if let Some(rule) = map.get(rest) {
+            return Some(rule);
+        }
+        rest = rest.split_once('.')?.1;
+    }
+}
+
+/// Match `text` against a glob where `*` is any run of characters and `?` is one character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t) = (pattern.as_bytes(), text.as_bytes());
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last '*' seen and the text index it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(list: &[(&str, RuleKind)]) -> UserRules {
+        let mut r = UserRules::default();
+        for (d, k) in list {
+            r.insert(Rule::new(d, *k, None).unwrap());
+        }
+        r
+    }
+
+    #[test]
+    fn test_rule_kinds() {
+        let r = rules(&[
+            ("example.com", RuleKind::Suffix),
+            ("tracker.net", RuleKind::Exact),
+            ("*.ads.*", RuleKind::Glob),
+        ]);
+        assert!(r.is_blocked("example.com"));
+        assert!(r.is_blocked("sub.example.com"));
+        assert!(r.is_blocked("tracker.net"));
+        assert!(!r.is_blocked("cdn.tracker.net"));
+        assert!(r.is_blocked("foo.ads.example.org"));
+        assert!(!r.is_blocked("ads.example.org"));
+        assert!(!r.is_blocked("notexample.com"));
+    }
+
+    #[test]
+    fn test_allow_overrides_block() {
+        let r = rules(&[
+            ("google.com", RuleKind::Suffix),
+            ("*.google.*", RuleKind::Glob),
+            ("mail.google.com", RuleKind::Allow),
+        ]);
+        assert!(r.is_blocked("google.com"));
+        assert!(r.is_blocked("www.google.com"));
+        assert!(!r.is_blocked("mail.google.com"));
+        assert!(!r.is_blocked("inbox.mail.google.com"));
+    }
+
+    #[tokio::test]
+    async fn test_device_rules_merge_with_user_rules() {
+        let cache = RulesCache::new();
+        let (user, laptop, phone) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache
+            .set_rules(user, None, vec![Rule::new("linkedin.com", RuleKind::Suffix, None).unwrap()])
+            .await;
+        cache
+            .set_rules(user, Some(laptop), vec![Rule::new("linkedin.com", RuleKind::Allow, None).unwrap()])
+            .await;
+        cache
+            .set_rules(user, Some(phone), vec![Rule::new("reddit.com", RuleKind::Suffix, None).unwrap()])
+            .await;
+
+        let blocked = Some(Verdict::Block(None));
+        assert_eq!(cache.verdict(&user, &laptop, "www.linkedin.com", None).await, Some(Verdict::Allow));
+        assert_eq!(cache.verdict(&user, &phone, "www.linkedin.com", None).await, blocked);
+        assert_eq!(cache.verdict(&user, &phone, "reddit.com", None).await, blocked);
+        assert_eq!(cache.verdict(&user, &laptop, "reddit.com", None).await, None);
+        assert_eq!(cache.verdict(&user, &Uuid::nil(), "reddit.com", None).await, None);
+    }
+
+    #[test]
+    fn test_category_block_with_allow_exception() {
+        let r = rules(&[("social", RuleKind::Category), ("linkedin.com", RuleKind::Allow)]);
+        let social = Some(Category::Social as i32);
+        let now = Utc::now();
+        assert_eq!(r.verdict("www.reddit.com", social, now), Some(Verdict::Block(None)));
+        assert_eq!(r.verdict("www.linkedin.com", social, now), Some(Verdict::Allow));
+        assert_eq!(r.verdict("github.com", Some(Category::Tech as i32), now), None);
+        assert!(Rule::new("nonsense", RuleKind::Category, None).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_rule() {
+        let schedule: Schedule = serde_json::from_str(
+            r#"{"days":["Mon","Tue","Wed","Thu","Fri"],"start":"09:00","end":"17:00","timezone":"Europe/Berlin"}"#,
+        )
+        .unwrap();
+        let mut r = UserRules::default();
+        r.insert(Rule::new("reddit.com", RuleKind::Suffix, Some(schedule)).unwrap());
+
+        // Wednesday 2026-01-14: 10:00 UTC = 11:00 Berlin (inside), 17:30 UTC = 18:30 Berlin (outside)
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(r.verdict("www.reddit.com", None, at("2026-01-14T10:00:00Z")), Some(Verdict::Block(None)));
+        assert_eq!(r.verdict("www.reddit.com", None, at("2026-01-14T17:30:00Z")), None);
+        // Saturday
+        assert_eq!(r.verdict("reddit.com", None, at("2026-01-17T10:00:00Z")), None);
+    }
+
+    #[test]
+    fn test_scheduled_rules_keep_their_identity() {
+        let window = |start: &str| -> Schedule {
+            serde_json::from_str(&format!(r#"{{"start":"{}","end":"23:59","timezone":"UTC"}}"#, start)).unwrap()
+        };
+        let morning = Rule::new("reddit.com", RuleKind::Exact, Some(window("06:00"))).unwrap();
+        let evening = Rule::new("reddit.com", RuleKind::Exact, Some(window("18:00"))).unwrap();
+        let refused = morning.clone().with_block_mode(Some(BlockMode::Refused)).unwrap();
+        let mut r = UserRules::default();
+        r.insert(morning.clone());
+        r.insert(evening.clone());
+        r.insert(refused);
+        assert_eq!(r.len(), 2);
+
+        // Removing one window leaves the other in place
+        r.remove(&morning);
+        assert_eq!(r.to_rules(), vec![evening]);
+
+        // An active scheduled exact rule outranks a category rule
+        let social = Rule::new("social", RuleKind::Category, None).unwrap();
+        r.insert(social.with_block_mode(Some(BlockMode::Nxdomain)).unwrap());
+        let at = "2026-01-14T20:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(r.verdict("reddit.com", Some(Category::Social as i32), at), Some(Verdict::Block(None)));
+        assert_eq!(
+            r.verdict("www.reddit.com", Some(Category::Social as i32), at),
+            Some(Verdict::Block(Some(BlockMode::Nxdomain)))
+        );
+
+        assert!(Rule::new("social", RuleKind::Category, Some(window("23:59"))).is_err());
+    }
+
+    #[test]
+    fn test_overnight_schedule() {
+        let schedule: Schedule = serde_json::from_str(
+            r#"{"days":["Fri"],"start":"22:00","end":"06:00","timezone":"UTC"}"#,
+        )
+        .unwrap();
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert!(schedule.is_active(at("2026-01-16T23:00:00Z"))); // Fri night
+        assert!(schedule.is_active(at("2026-01-17T05:59:00Z"))); // early Sat
+        assert!(!schedule.is_active(at("2026-01-17T23:00:00Z"))); // Sat night
+        assert!(!schedule.is_active(at("2026-01-16T05:00:00Z"))); // early Fri
+    }
+
+    #[tokio::test]
+    async fn test_block_mode_rule_overrides_user_default() {
+        let cache = RulesCache::new();
+        let user = Uuid::new_v4();
+        let refused = Rule::new("ads.example.com", RuleKind::Suffix, None)
+            .unwrap()
+            .with_block_mode(Some(BlockMode::Refused))
+            .unwrap();
+        cache
+            .set_rules(user, None, vec![refused, Rule::new("example.com", RuleKind::Suffix, None).unwrap()])
+            .await;
+
+        let nil = Uuid::nil();
+        assert_eq!(cache.verdict(&user, &nil, "x.example.com", None).await, Some(Verdict::Block(None)));
+        cache.set_block_mode(user, Some(BlockMode::Sinkhole)).await;
+        assert_eq!(
+            cache.verdict(&user, &nil, "x.example.com", None).await,
+            Some(Verdict::Block(Some(BlockMode::Sinkhole)))
+        );
+        // Closest suffix wins, and carries its own mode
+        assert_eq!(
+            cache.verdict(&user, &nil, "x.ads.example.com", None).await,
+            Some(Verdict::Block(Some(BlockMode::Refused)))
+        );
+        assert!(Rule::new("ok.com", RuleKind::Allow, None)
+            .unwrap()
+            .with_block_mode(Some(BlockMode::Nxdomain))
+            .is_err());
+    }
+
+    #[test]
+    fn test_rule_validation() {
+        assert!(Rule::new("*.ads.*", RuleKind::Suffix, None).is_err());
+        assert!(Rule::new("ads.com", RuleKind::Glob, None).is_err());
+        assert_eq!(Rule::new(" Example.COM. ", RuleKind::Exact, None).unwrap().domain, "example.com");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything.com"));
+        assert!(glob_match("ad?.example.com", "ads.example.com"));
+        assert!(!glob_match("ad?.example.com", "a.example.com"));
+        assert!(glob_match("*.doubleclick.*", "x.y.doubleclick.net"));
+    }
+}
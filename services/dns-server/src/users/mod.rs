@@ -1,6 +1,7 @@
 //! User management and VPN IP lookup
 
 use dashmap::DashMap;
+use std::collections::HashSet;
 use std::net::IpAddr;
 use std::sync::Arc;
 use uuid::Uuid;
@@ -14,15 +15,18 @@ pub struct CachedUser {
     pub vpn_ip: IpAddr,
 }
 
-/// Thread-safe user cache keyed by VPN IP
+/// Thread-safe user cache keyed by VPN IP (and DoT token for off-tunnel clients)
 pub struct UserCache {
     by_ip: Arc<DashMap<IpAddr, CachedUser>>,
+    /// DoT SNI token -> user (device_id is nil; tokens are per user)
+    by_dot_token: Arc<DashMap<String, CachedUser>>,
 }
 
 impl UserCache {
     pub fn new() -> Self {
         Self {
             by_ip: Arc::new(DashMap::new()),
+            by_dot_token: Arc::new(DashMap::new()),
         }
     }
 
@@ -41,12 +45,24 @@ impl UserCache {
         self.by_ip.remove(ip);
     }
 
+    /// Get user by DNS-over-TLS SNI token
+    pub fn get_by_dot_token(&self, token: &str) -> Option<CachedUser> {
+        self.by_dot_token.get(token).map(|r| r.value().clone())
+    }
+
+    /// Set the DoT token for a user, replacing any previous token
+    pub fn set_dot_token(&self, token: String, user: CachedUser) {
+        self.by_dot_token.retain(|_, u| u.user_id != user.user_id);
+        self.by_dot_token.insert(token, user);
+    }
+
     /// Get number of cached users
     pub fn len(&self) -> usize {
         self.by_ip.len()
     }
 
-    /// Load all users from database into cache
+    /// Load all users from database into cache, dropping entries for
+    /// devices and tokens that no longer exist
     pub async fn load_from_db(&self, db: &sqlx::PgPool) -> anyhow::Result<()> {
         let rows = sqlx::query_as::<_, (Uuid, String, Uuid, String)>(
             r#"
@@ -58,8 +74,10 @@ impl UserCache {
         .fetch_all(db)
         .await?;
 
+        let mut ips = HashSet::new();
         for (user_id, wallet_address, device_id, vpn_ip_str) in rows {
             if let Ok(vpn_ip) = vpn_ip_str.parse::<IpAddr>() {
+                ips.insert(vpn_ip);
                 self.upsert(CachedUser {
                     user_id,
                     wallet_address,
@@ -68,8 +86,31 @@ impl UserCache {
                 });
             }
         }
+        self.by_ip.retain(|ip, _| ips.contains(ip));
+
+        let tokens = sqlx::query_as::<_, (Uuid, String, String)>(
+            "SELECT id, wallet_address, dot_token FROM users WHERE dot_token IS NOT NULL"
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut current = HashSet::new();
+        for (user_id, wallet_address, token) in tokens {
+            current.insert(token.clone());
+            self.by_dot_token.insert(token, CachedUser {
+                user_id,
+                wallet_address,
+                device_id: Uuid::nil(),
+                vpn_ip: IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            });
+        }
+        self.by_dot_token.retain(|token, _| current.contains(token));
 
-        tracing::info!("Loaded {} devices into user cache", self.by_ip.len());
+        tracing::info!(
+            "Loaded {} devices and {} DoT tokens into user cache",
+            self.by_ip.len(),
+            self.by_dot_token.len()
+        );
         Ok(())
     }
 }